@@ -0,0 +1,23 @@
+//! Picks the memory layout: `memory/stlink.x` links at the start of flash,
+//! `memory/mks.x` (feature `mks-bootloader`) behind the Makerbase bootloader.
+//! Either one is copied to the output directory as the `memory.x` cortex-m-rt includes.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    let memory = if env::var_os("CARGO_FEATURE_MKS_BOOTLOADER").is_some() {
+        "memory/mks.x"
+    } else {
+        "memory/stlink.x"
+    };
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::copy(memory, out.join("memory.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=memory/stlink.x");
+    println!("cargo:rerun-if-changed=memory/mks.x");
+}
@@ -0,0 +1,6 @@
+/* Behind the Makerbase bootloader, which takes the first 28K (see src/boot.rs) */
+MEMORY
+{
+  FLASH : ORIGIN = 0x08007000, LENGTH = 256K - 28K
+  RAM : ORIGIN = 0x20000000, LENGTH = 64K
+}
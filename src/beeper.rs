@@ -0,0 +1,198 @@
+//
+// Piezo beeper on a PWM channel (PA2, TIM2 CH3 on the board)
+//
+// Notes queue up and play back to back without blocking: `next_note` starts
+// the next one and says how long it lasts, an RTIC task calls it again when
+// that time is up, e.g.
+//
+//   #[task(resources = [beeper], schedule = [beep])]
+//   fn beep(cx: beep::Context) {
+//       if let Some(ms) = cx.resources.beeper.next_note() {
+//           cx.schedule.beep(cx.scheduled + Duration::from_cycles(ms * CYCLES_PER_MS)).ok();
+//       }
+//   }
+//
+// Volume is the duty cycle, a piezo is loudest at 50%.
+//
+use embedded_hal::Pwm;
+use stm32f1xx_hal::time::Hertz;
+
+/// Notes waiting to play
+pub const QUEUE_LEN: usize = 16;
+
+/// Lowest and highest tone played, out of range frequencies are clamped
+pub const MIN_FREQ: u16 = 20;
+pub const MAX_FREQ: u16 = 20_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BeeperError {
+    /// Note queue is full
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Note {
+    /// Hz, 0 is a rest
+    pub freq: u16,
+    pub ms: u16,
+}
+
+impl Note {
+    pub const fn new(freq: u16, ms: u16) -> Self {
+        Note { freq, ms }
+    }
+
+    pub const fn rest(ms: u16) -> Self {
+        Note { freq: 0, ms }
+    }
+}
+
+/// Key click feedback
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClickConfig {
+    pub enabled: bool,
+    /// Hz
+    pub freq: u16,
+    pub ms: u16,
+}
+
+impl ClickConfig {
+    /// The click to play, None when turned off
+    pub fn note(&self) -> Option<Note> {
+        if self.enabled && self.freq > 0 && self.ms > 0 {
+            Some(Note::new(self.freq, self.ms))
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for ClickConfig {
+    fn default() -> Self {
+        ClickConfig {
+            enabled: true,
+            freq: 4_000,
+            ms: 8,
+        }
+    }
+}
+
+pub struct Beeper<P>
+where
+    P: Pwm,
+{
+    pwm: P,
+    channel: P::Channel,
+    /// Percent, 0..=100
+    volume: u8,
+    queue: [Note; QUEUE_LEN],
+    head: usize,
+    len: usize,
+    playing: bool,
+}
+
+impl<P> Beeper<P>
+where
+    P: Pwm<Time = Hertz, Duty = u16>,
+    P::Channel: Copy,
+{
+    /// Starts silent at full volume
+    pub fn new(mut pwm: P, channel: P::Channel) -> Self {
+        pwm.disable(channel);
+        Beeper {
+            pwm,
+            channel,
+            volume: 100,
+            queue: [Note::rest(0); QUEUE_LEN],
+            head: 0,
+            len: 0,
+            playing: false,
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    /// Percent, 0 mutes, applies from the next note
+    pub fn set_volume(&mut self, volume: u8) {
+        self.volume = volume.min(100);
+    }
+
+    /// Sounds `freq` until `silence`, whatever the queue does
+    pub fn tone(&mut self, freq: u16) {
+        if freq == 0 || self.volume == 0 {
+            self.silence();
+            return;
+        }
+        self.pwm
+            .set_period(Hertz(freq.clamp(MIN_FREQ, MAX_FREQ) as u32));
+        // 100% volume is 50% duty
+        let duty = (self.pwm.get_max_duty() as u32 * self.volume as u32 / 200) as u16;
+        self.pwm.set_duty(self.channel, duty);
+        self.pwm.enable(self.channel);
+    }
+
+    pub fn silence(&mut self) {
+        self.pwm.disable(self.channel);
+    }
+
+    /// Queues a note, when nothing `is_playing` the caller has to get `next_note` going
+    pub fn play(&mut self, note: Note) -> Result<(), BeeperError> {
+        if self.len == QUEUE_LEN {
+            return Err(BeeperError::Full);
+        }
+        self.queue[(self.head + self.len) % QUEUE_LEN] = note;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Queues notes, as many as fit
+    pub fn play_all(&mut self, notes: &[Note]) -> Result<(), BeeperError> {
+        notes.iter().try_for_each(|n| self.play(*n))
+    }
+
+    /// Starts the next note, returns how many ms it lasts,
+    /// None (and silence) once the queue ran out
+    pub fn next_note(&mut self) -> Option<u32> {
+        if self.len == 0 {
+            self.silence();
+            self.playing = false;
+            return None;
+        }
+
+        let note = self.queue[self.head];
+        self.head = (self.head + 1) % QUEUE_LEN;
+        self.len -= 1;
+
+        self.tone(note.freq);
+        self.playing = true;
+        Some(note.ms as u32)
+    }
+
+    /// Drops the queued notes, the one playing ends on the next `next_note`
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Drops everything and goes quiet now
+    pub fn stop(&mut self) {
+        self.clear();
+        self.silence();
+    }
+
+    /// A note is sounding (or a rest running), `next_note` is due
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Notes waiting
+    pub fn queued(&self) -> usize {
+        self.len
+    }
+
+    /// Gives back the timer
+    pub fn release(self) -> P {
+        self.pwm
+    }
+}
@@ -0,0 +1,143 @@
+#![cfg_attr(not(doc), no_main)]
+#![no_std]
+
+use core::fmt::{self, Write};
+
+use cortex_m::asm;
+use cortex_m_semihosting::hprintln;
+
+use rtic::cyccnt::Instant;
+
+use stm32_rust_rtic_blink::{board::Board, delay::*, lcd::*};
+
+use embedded_graphics::{
+    fonts::{Font6x8, Text},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{Circle, Line, Rectangle},
+    style::{PrimitiveStyle, TextStyle},
+};
+
+/// Cycles spent in `f`
+fn cycles<F: FnOnce()>(f: F) -> u32 {
+    let start = Instant::now();
+    f();
+    Instant::now().duration_since(start).as_cycles()
+}
+
+/// Text line formatted on the stack
+struct Line40 {
+    buf: [u8; 40],
+    len: usize,
+}
+
+impl Line40 {
+    fn new() -> Self {
+        Line40 {
+            buf: [0; 40],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Line40 {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Draws `item` pixel by pixel, then through the `Lcd` fast paths
+/// (spans, batched `draw_iter`), and reports both timings
+fn compare<'a, T>(lcd: &mut Lcd<DwtDelay>, row: i32, name: &str, item: &'a T)
+where
+    &'a T: IntoIterator<Item = Pixel<Rgb565>> + Drawable<Rgb565>,
+{
+    let per_pixel = cycles(|| {
+        for p in item.into_iter() {
+            lcd.draw_pixel(p).unwrap();
+        }
+    });
+    let fast = cycles(|| item.draw(lcd).unwrap());
+
+    hprintln!("{}: {} -> {} cycles", name, per_pixel, fast).unwrap();
+
+    let mut line = Line40::new();
+    write!(
+        line,
+        "{:7} {:>9} {:>9} x{}",
+        name,
+        per_pixel,
+        fast,
+        per_pixel / fast.max(1)
+    )
+    .unwrap();
+    Text::new(line.as_str(), Point::new(0, 200 + row * 10))
+        .into_styled(TextStyle::new(Font6x8, Rgb565::WHITE))
+        .draw(lcd)
+        .unwrap();
+}
+
+#[rtic::app(device = stm32f1xx_hal::stm32,
+            peripherals = true,
+            monotonic = rtic::cyccnt::CYCCNT)]
+const APP: () = {
+    struct Resources {
+        lcd: Lcd<DwtDelay>,
+    }
+
+    #[init]
+    fn init(cx: init::Context) -> init::LateResources {
+        let mut core = cx.core;
+        // also starts the cycle counter the benchmark measures with
+        let board = Board::new(cx.device, &mut core.DCB, &mut core.DWT).unwrap();
+
+        init::LateResources { lcd: board.lcd }
+    }
+
+    #[idle(resources = [lcd])]
+    fn idle(ctx: idle::Context) -> ! {
+        let lcd = ctx.resources.lcd;
+        lcd.init().unwrap();
+        lcd.clear(Rgb565::BLACK).unwrap();
+
+        Text::new("shape   per pixel      fast", Point::new(0, 190))
+            .into_styled(TextStyle::new(Font6x8, Rgb565::YELLOW))
+            .draw(lcd)
+            .unwrap();
+
+        let hline = Line::new(Point::new(0, 5), Point::new(239, 5))
+            .into_styled(PrimitiveStyle::with_stroke(Rgb565::WHITE, 1));
+        let vline = Line::new(Point::new(5, 10), Point::new(5, 180))
+            .into_styled(PrimitiveStyle::with_stroke(Rgb565::WHITE, 1));
+        let line = Line::new(Point::new(10, 10), Point::new(230, 60))
+            .into_styled(PrimitiveStyle::with_stroke(Rgb565::GREEN, 1));
+        let rect = Rectangle::new(Point::new(10, 70), Point::new(120, 180))
+            .into_styled(PrimitiveStyle::with_stroke(Rgb565::RED, 3));
+        let circle = Circle::new(Point::new(180, 125), 50)
+            .into_styled(PrimitiveStyle::with_stroke(Rgb565::CYAN, 1));
+        let disc = Circle::new(Point::new(180, 125), 30)
+            .into_styled(PrimitiveStyle::with_fill(Rgb565::BLUE));
+
+        let text = Text::new("quick brown fox", Point::new(130, 178))
+            .into_styled(TextStyle::new(Font6x8, Rgb565::WHITE));
+
+        compare(lcd, 0, "hline", &hline);
+        compare(lcd, 1, "vline", &vline);
+        compare(lcd, 2, "line", &line);
+        compare(lcd, 3, "rect", &rect);
+        compare(lcd, 4, "circle", &circle);
+        compare(lcd, 5, "disc", &disc);
+        compare(lcd, 6, "text", &text);
+
+        loop {
+            asm::wfi();
+        }
+    }
+};
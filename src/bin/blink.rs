@@ -1,18 +1,23 @@
 #![cfg_attr(not(doc), no_main)]
 #![no_std]
 
-use panic_halt as _;
-
 use core::convert::TryFrom;
 
 use cortex_m::asm;
 //use cortex_m_semihosting::hprintln;
 
-use stm32f1xx_hal::prelude::*;
-
 use rtic::cyccnt::Duration;
 
-use stm32_rust_rtic_blink::{consts::*, delay::*, lcd::*, types::*};
+use stm32_rust_rtic_blink::{
+    beeper::*,
+    board::Board,
+    consts::*,
+    delay::*,
+    fault,
+    lcd::*,
+    melody::{sounds, Melody, Player, Priority},
+    types::*,
+};
 
 use embedded_graphics::{
     egcircle, egrectangle,
@@ -23,56 +28,34 @@ use embedded_graphics::{
     style::TextStyleBuilder,
 };
 
+/// Start up chirp
+const HELLO: &[Note] = &[Note::new(1_000, 60), Note::rest(30), Note::new(1_500, 60)];
+
 #[rtic::app(device = stm32f1xx_hal::stm32,
             peripherals = true,
             monotonic = rtic::cyccnt::CYCCNT)]
 const APP: () = {
     struct Resources {
-        beeper: BeeperPin,
-        lcd: Lcd<AsmDelay>,
+        beeper: Beeper<BeeperPwm>,
+        player: Player,
+        lcd: Lcd<DwtDelay>,
         cnt: u32,
     }
 
-    #[init(schedule = [blink])]
+    #[init(schedule = [blink], spawn = [beep])]
     fn init(cx: init::Context) -> init::LateResources {
         let mut core: rtic::Peripherals = cx.core;
-        let device = cx.device;
-        let mut flash = device.FLASH.constrain();
-        let mut rcc = device.RCC.constrain();
-
-        let _clocks = rcc
-            .cfgr
-            .use_hse(8.mhz()) // TODO: should be 25Mhz!
-            .sysclk(SYS_FREQ)
-            .pclk1(36.mhz())
-            .freeze(&mut flash.acr);
-
-        //assert!(clocks.usbclk_valid());
-
-        let mut gpioa = device.GPIOA.split(&mut rcc.apb2);
-        let mut gpiob = device.GPIOB.split(&mut rcc.apb2);
-        let mut gpioc = device.GPIOC.split(&mut rcc.apb2);
-        let mut gpiod = device.GPIOD.split(&mut rcc.apb2);
-
-        let beeper = gpioa.pa2.into_push_pull_output(&mut gpioa.crl);
-
-        let lcd = Lcd::new(
-            AsmDelay,
-            device.GPIOE,
-            &mut rcc.apb2,
-            gpiod.pd14.into_push_pull_output(&mut gpiod.crh),
-            gpioc.pc8.into_push_pull_output(&mut gpioc.crh),
-            gpiod.pd13.into_push_pull_output(&mut gpiod.crh),
-            gpiob.pb14.into_push_pull_output(&mut gpiob.crh),
-            gpiod.pd15.into_push_pull_output(&mut gpiod.crh),
-        )
-        .unwrap();
-
-        // Initialize (enable) the monotonic timer (CYCCNT)
-        core.DCB.enable_trace();
-        // required on Cortex-M7 devices that software lock the DWT (e.g. STM32F7)
-        cortex_m::peripheral::DWT::unlock();
-        core.DWT.enable_cycle_counter();
+        // also initializes (enables) the monotonic timer (CYCCNT)
+        let board = Board::new(cx.device, &mut core.DCB, &mut core.DWT).unwrap();
+
+        //assert!(board.clocks.usbclk_valid());
+
+        let spawn = cx.spawn;
+        let mut beeper = board.beeper;
+        let mut player = Player::new();
+        player.start(&mut beeper, Melody::Notes(HELLO), Priority::Notify, |g| {
+            spawn.beep(g)
+        });
 
         cx.schedule
             .blink(cx.start + Duration::from_cycles(SYS_FREQ.0 / 2))
@@ -81,7 +64,8 @@ const APP: () = {
         //hprintln!("init::LateResources").unwrap();
         init::LateResources {
             beeper,
-            lcd,
+            player,
+            lcd: board.lcd,
             cnt: 0,
         }
     }
@@ -91,6 +75,12 @@ const APP: () = {
         let lcd = ctx.resources.lcd;
         lcd.init().unwrap();
 
+        // what went wrong before the reset
+        if let Some(report) = fault::take_last() {
+            fault::draw(lcd, &report).unwrap();
+            asm::delay(SYS_FREQ.0 * 3);
+        }
+
         let mut r = 1u32;
         loop {
             let c = match r % 3 {
@@ -141,13 +131,27 @@ const APP: () = {
         }
     }
 
-    #[task(resources = [beeper, cnt],
+    #[task(resources = [beeper, player, cnt],
            schedule = [blink],
+           spawn = [beep],
            priority = 1)]
     fn blink(cx: blink::Context) {
         let n = cx.resources.cnt;
         *n += 1;
 
+        // a click every 10 blinks, the "print complete" tune every minute
+        let sound = match *n {
+            n if n % 120 == 0 => Some((sounds::PRINT_COMPLETE, Priority::Notify)),
+            n if n % 10 == 0 => Some((sounds::CLICK, Priority::Click)),
+            _ => None,
+        };
+        if let Some((melody, priority)) = sound {
+            let spawn = cx.spawn;
+            cx.resources
+                .player
+                .start(cx.resources.beeper, melody, priority, |g| spawn.beep(g));
+        }
+
         //hprintln!("n={}", n).unwrap();
         //cx.resources.lcd_data.odr.write(|w| unsafe { w.bits(*n) });
 
@@ -156,6 +160,24 @@ const APP: () = {
             .unwrap();
     }
 
+    /// Plays melody `generation` note by note, stops once another one started
+    #[task(resources = [beeper, player],
+           schedule = [beep],
+           capacity = 2,
+           priority = 1)]
+    fn beep(cx: beep::Context, generation: u32) {
+        let r = cx.resources;
+        if let Some(ms) = r.player.next(r.beeper, generation) {
+            cx.schedule
+                .beep(
+                    cx.scheduled
+                        + Duration::from_cycles((ms as u64 * SYS_FREQ.0 as u64 / 1_000) as u32),
+                    generation,
+                )
+                .ok();
+        }
+    }
+
     // RTIC requires that unused interrupts are declared in an extern block when
     // using software tasks; these free interrupts will be used to dispatch the
     // software tasks.
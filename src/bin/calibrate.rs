@@ -0,0 +1,129 @@
+#![cfg_attr(not(doc), no_main)]
+#![no_std]
+
+use core::convert::TryFrom;
+
+use cortex_m::asm;
+
+use stm32_rust_rtic_blink::{
+    board::{Board, EEPROM_VARIANT},
+    consts::*,
+    delay::*,
+    lcd::*,
+    settings::{At24c, Store},
+    touch::{calibration::MAX_POINTS, Calibrator, Touch, Xpt2046},
+    types::*,
+};
+
+use embedded_graphics::{
+    fonts::{Font6x8, Text},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{Circle, Rectangle},
+    style::{PrimitiveStyle, TextStyle},
+};
+
+/// Status line, clear of the targets
+fn message(lcd: &mut Lcd<DwtDelay>, text: &str) {
+    Rectangle::new(Point::new(0, 100), Point::new(lcd.max_btm_right().x, 110))
+        .into_styled(PrimitiveStyle::with_fill(Rgb565::BLACK))
+        .draw(lcd)
+        .unwrap();
+    Text::new(text, Point::new(40, 100))
+        .into_styled(TextStyle::new(Font6x8, Rgb565::WHITE))
+        .draw(lcd)
+        .unwrap();
+}
+
+#[rtic::app(device = stm32f1xx_hal::stm32,
+            peripherals = true,
+            monotonic = rtic::cyccnt::CYCCNT)]
+const APP: () = {
+    struct Resources {
+        lcd: Lcd<DwtDelay>,
+        touch: Xpt2046<Spi1, TouchCsPin>,
+        store: Store<I2c1>,
+    }
+
+    #[init]
+    fn init(cx: init::Context) -> init::LateResources {
+        let mut core = cx.core;
+        let board = Board::new(cx.device, &mut core.DCB, &mut core.DWT).unwrap();
+
+        let eeprom = At24c::new(board.eeprom, EEPROM_VARIANT, 0);
+        let store = Store::new(eeprom, 0, EEPROM_VARIANT.size).unwrap();
+
+        init::LateResources {
+            lcd: board.lcd,
+            touch: board.touch,
+            store,
+        }
+    }
+
+    #[idle(resources = [lcd, touch, store])]
+    fn idle(ctx: idle::Context) -> ! {
+        let lcd = ctx.resources.lcd;
+        let touch = ctx.resources.touch;
+        let store = ctx.resources.store;
+        lcd.init().unwrap();
+        lcd.clear(Rgb565::BLACK).unwrap();
+
+        let mut calibrator = Calibrator::new(MAX_POINTS, lcd.rotation()).unwrap();
+        message(lcd, "Touch the crosses");
+
+        let calibration = loop {
+            calibrator
+                .render(lcd, Rgb565::WHITE, Rgb565::BLACK)
+                .unwrap();
+            if let Ok(Some(t)) = touch.poll() {
+                match calibrator.handle_touch(t) {
+                    Some(Ok(calibration)) => break calibration,
+                    Some(Err(_)) => {
+                        message(lcd, "Missed, once more");
+                        calibrator.restart();
+                    }
+                    None => {}
+                }
+            }
+            asm::delay(SYS_FREQ.0 / 100);
+        };
+        calibrator
+            .render(lcd, Rgb565::WHITE, Rgb565::BLACK)
+            .unwrap();
+
+        // kept in the settings, the other fields as they were
+        let saved = store.load().and_then(|(mut settings, _)| {
+            settings.calibration = calibration;
+            store.save(&settings)
+        });
+        // still good for this run, the next one starts uncalibrated
+        if saved.is_err() {
+            message(lcd, "Saving failed");
+            asm::delay(SYS_FREQ.0 * 2);
+        }
+        message(lcd, "Draw, rotates on release");
+
+        // the same calibration in every rotation
+        let mut rotation = 0;
+        loop {
+            if let Ok(Some(t)) = touch.poll() {
+                match t {
+                    Touch::Down(s) | Touch::Move(s) => {
+                        Circle::new(calibration.map(&s, lcd.rotation()), 2)
+                            .into_styled(PrimitiveStyle::with_fill(Rgb565::GREEN))
+                            .draw(lcd)
+                            .unwrap();
+                    }
+                    Touch::Up => {
+                        rotation = (rotation + 1) % 4;
+                        lcd.set_rotation(Rotation::try_from(rotation).unwrap())
+                            .unwrap();
+                        lcd.clear(Rgb565::BLACK).unwrap();
+                        message(lcd, "Draw, rotates on release");
+                    }
+                }
+            }
+            asm::delay(SYS_FREQ.0 / 100);
+        }
+    }
+};
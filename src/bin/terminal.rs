@@ -0,0 +1,149 @@
+#![cfg_attr(not(doc), no_main)]
+#![no_std]
+
+use cortex_m::peripheral::DWT;
+
+use stm32f1xx_hal::{prelude::*, serial::Rx, stm32::USART1};
+
+use rtic::cyccnt::Duration;
+
+use stm32_rust_rtic_blink::{
+    board::{self, Board, EEPROM_VARIANT},
+    consts::*,
+    delay::*,
+    lcd::*,
+    settings::{At24c, Settings, Store},
+    terminal::*,
+    watchdog::{Supervisor, TaskId},
+};
+
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
+
+/// Resets unless fed this often
+const WATCHDOG_TIMEOUT_MS: u32 = 1_000;
+/// Fed this often, while the tasks are on time
+const SERVICE_PERIOD: u32 = SYS_FREQ.0 / 4;
+/// A full redraw
+const RENDER_DEADLINE_MS: u32 = 2_000;
+/// One byte through the emulator, scrolling included
+const FEED_DEADLINE_MS: u32 = 500;
+
+// Serial terminal: bytes received on USART1 (PA9 TX / PA10 RX)
+// are fed through the VT100 emulator and rendered on the LCD.
+#[rtic::app(device = stm32f1xx_hal::stm32,
+            peripherals = true,
+            monotonic = rtic::cyccnt::CYCCNT)]
+const APP: () = {
+    struct Resources {
+        lcd: Lcd<DwtDelay>,
+        rx: Rx<USART1>,
+        term: Terminal,
+        supervisor: Supervisor,
+        render: TaskId,
+        feeder: TaskId,
+        /// Stored brightness above 0
+        backlight: bool,
+    }
+
+    #[init(spawn = [supervise])]
+    fn init(cx: init::Context) -> init::LateResources {
+        let mut core = cx.core;
+        let board = Board::new(cx.device, &mut core.DCB, &mut core.DWT).unwrap();
+
+        // the stored baud, or SERIAL_BAUD when there's none (or no EEPROM)
+        let eeprom = At24c::new(board.eeprom, EEPROM_VARIANT, 0);
+        let settings = Store::new(eeprom, 0, EEPROM_VARIANT.size)
+            .and_then(|mut store| store.load())
+            .map_or_else(|_| Settings::default(), |(settings, _)| settings);
+        let mut serial = board.serial;
+        board::set_serial_baud(&mut serial, &board.clocks, settings.baud);
+
+        let (_tx, mut rx) = serial.split();
+        rx.listen();
+
+        let lcd = board.lcd;
+        let term = Terminal::new(lcd.size());
+
+        let now = DWT::cycle_count();
+        let mut supervisor = Supervisor::new(board.iwdg, &board.clocks);
+        let render = supervisor
+            .register("render loop", RENDER_DEADLINE_MS, now)
+            .unwrap();
+        // busy only while feeding a byte
+        let feeder = supervisor
+            .register("serial feed", FEED_DEADLINE_MS, now)
+            .unwrap();
+        supervisor.pause(feeder);
+        supervisor.start(WATCHDOG_TIMEOUT_MS);
+        cx.spawn.supervise().unwrap();
+
+        init::LateResources {
+            lcd,
+            rx,
+            term,
+            supervisor,
+            render,
+            feeder,
+            backlight: settings.brightness > 0,
+        }
+    }
+
+    #[idle(resources = [lcd, term, supervisor, render, backlight])]
+    fn idle(cx: idle::Context) -> ! {
+        let lcd = cx.resources.lcd;
+        let mut term = cx.resources.term;
+        let mut supervisor = cx.resources.supervisor;
+        let render = *cx.resources.render;
+
+        lcd.init().unwrap();
+        lcd.set_backlight(*cx.resources.backlight).unwrap();
+        lcd.clear(Rgb565::BLACK).unwrap();
+
+        loop {
+            supervisor.lock(|s| s.check_in(render, DWT::cycle_count()));
+            // one row per lock, so incoming bytes are not held up by a full redraw
+            while term.lock(|t| t.render_next(lcd)).unwrap() {}
+        }
+    }
+
+    #[task(binds = USART1,
+           resources = [rx],
+           spawn = [feed],
+           priority = 2)]
+    fn usart1(cx: usart1::Context) {
+        while let Ok(b) = cx.resources.rx.read() {
+            // drops bytes when the terminal falls behind
+            let _ = cx.spawn.feed(b);
+        }
+    }
+
+    #[task(resources = [term, supervisor, feeder],
+           capacity = 64,
+           priority = 1)]
+    fn feed(cx: feed::Context, b: u8) {
+        let mut r = cx.resources;
+        let feeder = *r.feeder;
+        r.supervisor
+            .lock(|s| s.check_in(feeder, DWT::cycle_count()));
+        r.term.write_byte(b);
+        r.supervisor.lock(|s| s.pause(feeder));
+    }
+
+    /// Feeds the IWDG while everyone checks in, above the tasks it watches
+    #[task(resources = [supervisor],
+           schedule = [supervise],
+           priority = 3)]
+    fn supervise(cx: supervise::Context) {
+        // a late task stops the feeding, the IWDG resets
+        cx.resources.supervisor.service(DWT::cycle_count()).ok();
+        cx.schedule
+            .supervise(cx.scheduled + Duration::from_cycles(SERVICE_PERIOD))
+            .ok();
+    }
+
+    // Full list in  stm32f1::stm32f103::Interrupt
+    extern "C" {
+        fn EXTI4();
+        fn FSMC();
+    }
+};
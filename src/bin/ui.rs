@@ -0,0 +1,551 @@
+#![cfg_attr(not(doc), no_main)]
+#![no_std]
+
+use cortex_m::{asm, peripheral::DWT};
+use embedded_hal::digital::v2::InputPin;
+
+use stm32f1xx_hal::{gpio::ExtiPin, pac::EXTI};
+
+use rtic::cyccnt::Duration;
+
+use stm32_rust_rtic_blink::{
+    beeper::{Beeper, ClickConfig},
+    board::{Board, EEPROM_VARIANT},
+    consts::*,
+    delay::*,
+    fault,
+    lcd::*,
+    melody::{Melody, Player, Priority},
+    settings::{At24c, Settings as StoredSettings, Store},
+    touch::{Calibration, Gesture, GestureConfig, GestureQueue, Gestures, Swipe, Touch, Xpt2046},
+    types::*,
+    ui::*,
+    watchdog::{self, ResetCause, Supervisor, TaskId},
+};
+
+use embedded_graphics::{
+    fonts::{Font6x8, Text},
+    geometry::{Point, Size},
+    pixelcolor::Rgb565,
+    prelude::*,
+    style::{PrimitiveStyleBuilder, TextStyle},
+};
+
+type Display = Lcd<DwtDelay>;
+
+/// Pen sampled this often while down
+const SAMPLE_PERIOD: u32 = SYS_FREQ.0 / 100;
+
+/// Resets unless fed this often
+const WATCHDOG_TIMEOUT_MS: u32 = 1_000;
+/// Fed this often, while the tasks are on time
+const SERVICE_PERIOD: u32 = SYS_FREQ.0 / 4;
+/// A drawing loop pass, slides included, with room to spare
+const UI_DEADLINE_MS: u32 = 2_000;
+/// Ten samples missed while the pen is down
+const SAMPLER_DEADLINE_MS: u32 = 100;
+
+const HOME: ScreenId = ScreenId(0);
+const SETTINGS: ScreenId = ScreenId(1);
+const CONFIRM: ScreenId = ScreenId(2);
+
+const ITEMS: &[&str] = &["Red", "Green", "Blue", "Cyan", "Magenta", "Yellow", "White"];
+
+/// Key presses played back until the panel is touched
+const SCRIPT: &[Event] = &[
+    Event::FocusNext,
+    Event::Increment,
+    Event::Increment,
+    Event::FocusNext,
+    Event::Activate,
+    Event::FocusNext,
+    Event::Increment,
+    Event::FocusNext,
+    Event::Activate,
+    Event::FocusNext,
+    Event::FocusNext,
+    Event::Activate,
+    Event::FocusNext,
+    Event::Activate,
+];
+
+fn dispatch(nav: &mut Navigator<'_, '_, Display>, event: Event) {
+    // e.g. pushed too deep, start over
+    if nav.handle_event(event).is_err() {
+        nav.navigate(Transition::Home).unwrap();
+    }
+}
+
+/// The whole screen but a margin, `screen` is the display size in its rotation
+fn screen_area(screen: Size) -> Area {
+    Area::new(Point::zero(), screen).inset(10)
+}
+
+struct Home<'w> {
+    ui: Ui<'static, 'w>,
+    status: WidgetId,
+    progress: WidgetId,
+    settings: WidgetId,
+    dialog: WidgetId,
+    list: WidgetId,
+    step: u32,
+}
+
+impl<'w> Home<'w> {
+    fn new(
+        storage: &'w mut [Option<Widget<'static>>],
+        feedback: &'w Feedback,
+        screen: Size,
+    ) -> Result<Self, UiError> {
+        let mut ui = Ui::new(storage, Theme::dark());
+        ui.set_feedback(feedback);
+
+        let mut column = Stack::column(screen_area(screen), 8);
+        let status = ui.add(Widget::label("Home"), column.next(12))?;
+        ui.add(Widget::slider(0, 100, 5, 50), column.next(20))?;
+        let progress = ui.add(Widget::progress(0), column.next(16))?;
+
+        let mut buttons = Stack::row(column.next(28), 8);
+        let mut halves = buttons.split(2);
+        let settings = ui.add(
+            Widget::button("Settings"),
+            halves.next().unwrap_or_default(),
+        )?;
+        let dialog = ui.add(Widget::button("Dialog"), halves.next().unwrap_or_default())?;
+
+        let list = ui.add(Widget::list(ITEMS), column.rest())?;
+
+        Ok(Home {
+            ui,
+            status,
+            progress,
+            settings,
+            dialog,
+            list,
+            step: 0,
+        })
+    }
+}
+
+impl<'w> Screen<Display> for Home<'w> {
+    fn enter(&mut self) {
+        self.ui.invalidate_all();
+    }
+
+    fn handle_event(&mut self, event: Event) -> Transition {
+        match self.ui.handle_event(event) {
+            Some(Response { id, .. }) if id == self.settings => Transition::Push(SETTINGS),
+            Some(Response { id, .. }) if id == self.dialog => Transition::Modal(CONFIRM),
+            Some(Response {
+                id,
+                action: Action::Selected(i),
+            }) if id == self.list => {
+                self.ui.set_text(self.status, ITEMS[i]);
+                Transition::Stay
+            }
+            _ => Transition::Stay,
+        }
+    }
+
+    fn render(&mut self, lcd: &mut Display) -> Result<(), LcdError> {
+        // keeps moving while the screen is up
+        self.step = (self.step + 1) % 101;
+        self.ui.set_value(self.progress, self.step as i32);
+        self.ui.render(lcd)
+    }
+
+    fn leave(&mut self) {}
+}
+
+struct Settings<'w> {
+    ui: Ui<'static, 'w>,
+    brightness: WidgetId,
+    /// Backlight switch the slider asked for, made on the next render
+    backlight: Option<bool>,
+    reset: WidgetId,
+    back: WidgetId,
+}
+
+impl<'w> Settings<'w> {
+    fn new(
+        storage: &'w mut [Option<Widget<'static>>],
+        feedback: &'w Feedback,
+        screen: Size,
+        brightness: u8,
+    ) -> Result<Self, UiError> {
+        let mut ui = Ui::new(storage, Theme::dark());
+        ui.set_feedback(feedback);
+
+        let mut column = Stack::column(screen_area(screen), 8);
+        ui.add(Widget::label("Settings"), column.next(12))?;
+        ui.add(Widget::label("Brightness"), column.next(12))?;
+        // tenths of the stored percentage, 0 switches the backlight off
+        let brightness = ui.add(
+            Widget::slider(0, 10, 1, (brightness as i32 + 9) / 10),
+            column.next(20),
+        )?;
+        let reset = ui.add(Widget::button("Reset"), column.next(28))?;
+        let back = ui.add(Widget::button("Back"), column.next(28))?;
+
+        Ok(Settings {
+            ui,
+            brightness,
+            backlight: None,
+            reset,
+            back,
+        })
+    }
+}
+
+impl<'w> Screen<Display> for Settings<'w> {
+    fn enter(&mut self) {
+        self.ui.invalidate_all();
+    }
+
+    fn handle_event(&mut self, event: Event) -> Transition {
+        match self.ui.handle_event(event) {
+            Some(Response {
+                id,
+                action: Action::Changed(v),
+            }) if id == self.brightness => {
+                self.backlight = Some(v > 0);
+                Transition::Stay
+            }
+            Some(Response { id, .. }) if id == self.reset => Transition::Modal(CONFIRM),
+            Some(Response { id, .. }) if id == self.back => Transition::Pop,
+            _ => Transition::Stay,
+        }
+    }
+
+    fn render(&mut self, lcd: &mut Display) -> Result<(), LcdError> {
+        if let Some(on) = self.backlight.take() {
+            lcd.set_backlight(on)?;
+        }
+        self.ui.render(lcd)
+    }
+
+    fn leave(&mut self) {}
+}
+
+/// Yes/No dialog in a box over the current screen
+struct Confirm<'w> {
+    ui: Ui<'static, 'w>,
+    area: Area,
+    frame: bool,
+}
+
+impl<'w> Confirm<'w> {
+    fn new(
+        storage: &'w mut [Option<Widget<'static>>],
+        feedback: &'w Feedback,
+        screen: Size,
+    ) -> Result<Self, UiError> {
+        let mut ui = Ui::new(storage, Theme::dark());
+        ui.set_feedback(feedback);
+        let size = Size::new(180, 80);
+        let area = Area::new(Area::new(Point::zero(), screen).center(size), size);
+
+        let mut column = Stack::column(area.inset(8), 8);
+        ui.add(Widget::label("Are you sure?"), column.next(12))?;
+        let mut buttons = Stack::row(column.next(28), 8);
+        for (text, area) in ["Yes", "No"].iter().zip(buttons.split(2)) {
+            ui.add(Widget::button(text), area)?;
+        }
+
+        Ok(Confirm {
+            ui,
+            area,
+            frame: true,
+        })
+    }
+}
+
+impl<'w> Screen<Display> for Confirm<'w> {
+    fn enter(&mut self) {
+        self.frame = true;
+        self.ui.invalidate_all();
+    }
+
+    fn handle_event(&mut self, event: Event) -> Transition {
+        match self.ui.handle_event(event) {
+            // either button closes the dialog
+            Some(Response {
+                action: Action::Clicked,
+                ..
+            }) => Transition::Pop,
+            _ => Transition::Stay,
+        }
+    }
+
+    fn render(&mut self, lcd: &mut Display) -> Result<(), LcdError> {
+        if self.frame {
+            let theme = *self.ui.theme();
+            self.area
+                .rectangle()
+                .into_styled(
+                    PrimitiveStyleBuilder::new()
+                        .fill_color(theme.background)
+                        .stroke_color(theme.focus)
+                        .stroke_width(2)
+                        .build(),
+                )
+                .draw(lcd)?;
+            self.frame = false;
+        }
+        self.ui.render(lcd)
+    }
+
+    fn leave(&mut self) {}
+}
+
+/// Why the last run ended, when it didn't end well
+fn last_run(lcd: &mut Display, cause: ResetCause) {
+    if let Some(report) = fault::take_last() {
+        fault::draw(lcd, &report).unwrap();
+    } else if let Some(starved) = watchdog::take_starved().filter(|_| cause.is_watchdog()) {
+        lcd.clear(Rgb565::BLACK).unwrap();
+        Text::new("Watchdog reset, stuck:", Point::new(8, 8))
+            .into_styled(TextStyle::new(Font6x8, Rgb565::RED))
+            .draw(lcd)
+            .unwrap();
+        Text::new(starved.name(), Point::new(8, 18))
+            .into_styled(TextStyle::new(Font6x8, Rgb565::WHITE))
+            .draw(lcd)
+            .unwrap();
+    } else {
+        return;
+    }
+    asm::delay(SYS_FREQ.0 * 3);
+}
+
+#[rtic::app(device = stm32f1xx_hal::stm32,
+            peripherals = true,
+            monotonic = rtic::cyccnt::CYCCNT)]
+const APP: () = {
+    struct Resources {
+        lcd: Display,
+        touch: Xpt2046<Spi1, TouchCsPin>,
+        pen: TouchIrqPin,
+        exti: EXTI,
+        calibration: Calibration,
+        rotation: Rotation,
+        brightness: u8,
+        recognizer: Gestures,
+        gestures: GestureQueue,
+        beeper: Beeper<BeeperPwm>,
+        player: Player,
+        click: ClickConfig,
+        reset_cause: ResetCause,
+        supervisor: Supervisor,
+        sampler: TaskId,
+    }
+
+    #[init(spawn = [supervise])]
+    fn init(cx: init::Context) -> init::LateResources {
+        let mut core = cx.core;
+        // also starts the monotonic timer (CYCCNT), schedules the sampling
+        let board = Board::new(cx.device, &mut core.DCB, &mut core.DWT).unwrap();
+
+        // the UI loop registers once it's past the boot messages, `idle` starts the IWDG
+        let mut supervisor = Supervisor::new(board.iwdg, &board.clocks);
+        let sampler = supervisor
+            .register("touch sampler", SAMPLER_DEADLINE_MS, DWT::cycle_count())
+            .unwrap();
+        supervisor.pause(sampler);
+        cx.spawn.supervise().unwrap();
+
+        // the calibrate binary keeps one, defaults when there's none (or no EEPROM)
+        let eeprom = At24c::new(board.eeprom, EEPROM_VARIANT, 0);
+        let settings = Store::new(eeprom, 0, EEPROM_VARIANT.size)
+            .and_then(|mut store| store.load())
+            .map_or_else(|_| StoredSettings::default(), |(settings, _)| settings);
+
+        // PENIRQ goes low on touch
+        let mut pen = board.touch_irq;
+        pen.enable_interrupt(&board.exti);
+
+        init::LateResources {
+            lcd: board.lcd,
+            touch: board.touch,
+            pen,
+            exti: board.exti,
+            calibration: settings.calibration,
+            rotation: settings.rotation,
+            brightness: settings.brightness,
+            recognizer: Gestures::new(GestureConfig::new(SYS_FREQ.0)),
+            gestures: GestureQueue::new(),
+            beeper: board.beeper,
+            player: Player::new(),
+            click: ClickConfig::default(),
+            reset_cause: board.reset_cause,
+            supervisor,
+            sampler,
+        }
+    }
+
+    #[idle(resources = [lcd, gestures, reset_cause, supervisor, &rotation, &brightness], spawn = [click])]
+    fn idle(ctx: idle::Context) -> ! {
+        let lcd = ctx.resources.lcd;
+        let mut gestures = ctx.resources.gestures;
+        let mut supervisor = ctx.resources.supervisor;
+        lcd.init().unwrap();
+        // the stored one, touches are mapped into it too
+        lcd.set_rotation(*ctx.resources.rotation).unwrap();
+        let brightness = *ctx.resources.brightness;
+        lcd.set_backlight(brightness > 0).unwrap();
+        last_run(lcd, *ctx.resources.reset_cause);
+
+        let ui_loop = supervisor.lock(|s| {
+            let id = s.register("ui loop", UI_DEADLINE_MS, DWT::cycle_count());
+            s.start(WATCHDOG_TIMEOUT_MS);
+            id.unwrap()
+        });
+
+        // presses the widgets took, clicked for
+        let feedback = Feedback::new();
+        let mut home_widgets: [Option<Widget>; 8] = Default::default();
+        let mut settings_widgets: [Option<Widget>; 8] = Default::default();
+        let mut confirm_widgets: [Option<Widget>; 4] = Default::default();
+
+        let screen = lcd.size();
+        let mut home = Home::new(&mut home_widgets, &feedback, screen).unwrap();
+        let mut settings =
+            Settings::new(&mut settings_widgets, &feedback, screen, brightness).unwrap();
+        let mut confirm = Confirm::new(&mut confirm_widgets, &feedback, screen).unwrap();
+
+        let mut screens: [&mut dyn Screen<Display>; 3] = [&mut home, &mut settings, &mut confirm];
+        let mut nav = Navigator::new(&mut screens, HOME, Theme::dark().background).unwrap();
+        nav.set_slides(true);
+
+        let mut step = 0usize;
+        // the script plays until the panel is touched
+        let mut scripted = true;
+        loop {
+            supervisor.lock(|s| s.check_in(ui_loop, DWT::cycle_count()));
+
+            while let Some(gesture) = gestures.lock(|q| q.pop()) {
+                scripted = false;
+                if let Some(event) = gesture.ui_event() {
+                    dispatch(&mut nav, event);
+                }
+                match gesture {
+                    Gesture::Swipe(Swipe::Right) => nav.navigate(Transition::Pop).unwrap(),
+                    Gesture::DoubleTap(_) => nav.navigate(Transition::Home).unwrap(),
+                    _ => {}
+                }
+            }
+
+            if feedback.take() > 0 {
+                ctx.spawn.click().ok();
+            }
+
+            if scripted && step.is_multiple_of(50) {
+                dispatch(&mut nav, SCRIPT[(step / 50) % SCRIPT.len()]);
+            }
+
+            nav.render_animated(lcd).unwrap();
+            step += 1;
+            asm::delay(SYS_FREQ.0 / 50);
+        }
+    }
+
+    /// Pen touched, sampling takes over until it lifts
+    #[task(binds = EXTI9_5,
+           resources = [pen, exti],
+           spawn = [sample],
+           priority = 2)]
+    fn pen_down(cx: pen_down::Context) {
+        let pen = cx.resources.pen;
+        pen.clear_interrupt_pending_bit();
+        // conversions toggle PENIRQ, off until the pen lifts
+        pen.disable_interrupt(cx.resources.exti);
+        cx.spawn.sample().ok();
+    }
+
+    #[task(resources = [touch, pen, exti, calibration, &rotation, recognizer, gestures, supervisor, sampler],
+           schedule = [sample],
+           priority = 2)]
+    fn sample(cx: sample::Context) {
+        let mut r = cx.resources;
+        let gestures = r.gestures;
+        let sampler = *r.sampler;
+        let now = DWT::cycle_count();
+        r.supervisor.lock(|s| s.check_in(sampler, now));
+
+        match r.touch.poll() {
+            Ok(Some(Touch::Down(s))) | Ok(Some(Touch::Move(s))) => {
+                let p = r.calibration.map(&s, *r.rotation);
+                r.recognizer.pen(Some(p), now, |g| {
+                    gestures.push(g);
+                });
+            }
+            Ok(Some(Touch::Up)) => r.recognizer.pen(None, now, |g| {
+                gestures.push(g);
+            }),
+            // unsettled, or too light to count yet
+            _ => {}
+        }
+
+        if r.touch.is_down() || r.pen.is_low().unwrap_or(false) {
+            cx.schedule
+                .sample(cx.scheduled + Duration::from_cycles(SAMPLE_PERIOD))
+                .ok();
+        } else {
+            r.supervisor.lock(|s| s.pause(sampler));
+            r.pen.clear_interrupt_pending_bit();
+            r.pen.enable_interrupt(r.exti);
+        }
+    }
+
+    /// Feeds the IWDG while everyone checks in, above the tasks it watches
+    #[task(resources = [supervisor],
+           schedule = [supervise],
+           priority = 3)]
+    fn supervise(cx: supervise::Context) {
+        // a late task stops the feeding, the IWDG resets
+        cx.resources.supervisor.service(DWT::cycle_count()).ok();
+        cx.schedule
+            .supervise(cx.scheduled + Duration::from_cycles(SERVICE_PERIOD))
+            .ok();
+    }
+
+    /// Tells a press registered, unless clicks are off
+    #[task(resources = [beeper, player, click],
+           spawn = [beep],
+           priority = 1)]
+    fn click(cx: click::Context) {
+        let r = cx.resources;
+        let spawn = cx.spawn;
+        if let Some(note) = r.click.note() {
+            r.player
+                .start(r.beeper, Melody::Tone(note), Priority::Click, |g| {
+                    spawn.beep(g)
+                });
+        }
+    }
+
+    /// Plays melody `generation` note by note, stops once another one started
+    #[task(resources = [beeper, player],
+           schedule = [beep],
+           capacity = 2,
+           priority = 1)]
+    fn beep(cx: beep::Context, generation: u32) {
+        let r = cx.resources;
+        if let Some(ms) = r.player.next(r.beeper, generation) {
+            cx.schedule
+                .beep(
+                    cx.scheduled
+                        + Duration::from_cycles((ms as u64 * SYS_FREQ.0 as u64 / 1_000) as u32),
+                    generation,
+                )
+                .ok();
+        }
+    }
+
+    // free interrupts dispatching the software tasks
+    extern "C" {
+        fn EXTI4();
+        fn FSMC();
+        fn SPI2();
+    }
+};
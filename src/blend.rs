@@ -0,0 +1,426 @@
+//
+// Alpha compositing: destination pixels are read back (LCD GRAM or a RAM tile),
+// blended with the source color and written out again.
+// Anti-aliased lines, circles and text are built on top of that.
+//
+// https://en.wikipedia.org/wiki/Xiaolin_Wu%27s_line_algorithm
+//
+use core::convert::Infallible;
+
+use embedded_graphics::{
+    drawable::Pixel,
+    fonts::{Font, Text},
+    geometry::{Point, Size},
+    pixelcolor::{BinaryColor, Rgb565},
+    prelude::*,
+    style::TextStyle,
+    DrawTarget,
+};
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use crate::lcd::{Lcd, LcdError};
+use crate::math::isqrt;
+
+/// Pixels blended per read / write round trip, a full row of the rotated screen
+const CHUNK: usize = 320;
+
+/// Largest supersampled glyph `Compositor::text` renders (a 24x32 font)
+const MAX_GLYPH: usize = 12 * 16;
+
+/// Targets that can give their pixels back, for read-modify-write blending
+pub trait ReadPixels: DrawTarget<Rgb565> {
+    /// Reads a window into `buf`, row by row
+    fn read_area(
+        &mut self,
+        top_left: Point,
+        size: Size,
+        buf: &mut [Rgb565],
+    ) -> Result<(), Self::Error>;
+
+    /// Writes a window from `buf`, row by row
+    fn write_area(
+        &mut self,
+        top_left: Point,
+        size: Size,
+        buf: &[Rgb565],
+    ) -> Result<(), Self::Error>;
+}
+
+impl<D> ReadPixels for Lcd<D>
+where
+    D: DelayMs<u16> + DelayUs<u16>,
+{
+    fn read_area(
+        &mut self,
+        top_left: Point,
+        size: Size,
+        buf: &mut [Rgb565],
+    ) -> Result<(), LcdError> {
+        self.read_pixels(top_left, size, buf)
+    }
+
+    fn write_area(&mut self, top_left: Point, size: Size, buf: &[Rgb565]) -> Result<(), LcdError> {
+        self.write_pixels(top_left, size, buf.iter().copied())
+    }
+}
+
+/// Mixes `src` over `dst`, alpha 255 is fully `src`
+pub fn blend(dst: Rgb565, src: Rgb565, alpha: u8) -> Rgb565 {
+    let a = alpha as u16;
+    let mix = |s: u8, d: u8| ((s as u16 * a + d as u16 * (255 - a) + 127) / 255) as u8;
+    Rgb565::new(
+        mix(src.r(), dst.r()),
+        mix(src.g(), dst.g()),
+        mix(src.b(), dst.b()),
+    )
+}
+
+/// Rgb565 off-screen area in RAM, blending there avoids slow GRAM reads.
+/// Uses its own coordinates, `flush` puts it on the LCD.
+pub struct Tile<'a> {
+    buf: &'a mut [Rgb565],
+    size: Size,
+}
+
+impl<'a> Tile<'a> {
+    /// `buf` needs at least `width * height` pixels
+    pub fn new(buf: &'a mut [Rgb565], size: Size) -> Option<Self> {
+        if buf.len() < (size.width * size.height) as usize {
+            None
+        } else {
+            Some(Tile { buf, size })
+        }
+    }
+
+    pub fn pixels(&self) -> &[Rgb565] {
+        &self.buf[..(self.size.width * self.size.height) as usize]
+    }
+
+    /// Writes the tile into an LCD window at `top_left`
+    pub fn flush<D>(&self, lcd: &mut Lcd<D>, top_left: Point) -> Result<(), LcdError>
+    where
+        D: DelayMs<u16> + DelayUs<u16>,
+    {
+        lcd.write_pixels(top_left, self.size, self.pixels().iter().copied())
+    }
+
+    fn index(&self, p: Point) -> Option<usize> {
+        if p.x >= 0 && p.y >= 0 && (p.x as u32) < self.size.width && (p.y as u32) < self.size.height
+        {
+            Some(p.y as usize * self.size.width as usize + p.x as usize)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> DrawTarget<Rgb565> for Tile<'a> {
+    type Error = Infallible;
+
+    fn draw_pixel(&mut self, pixel: Pixel<Rgb565>) -> Result<(), Self::Error> {
+        let Pixel(p, color) = pixel;
+        if let Some(i) = self.index(p) {
+            self.buf[i] = color;
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn clear(&mut self, color: Rgb565) -> Result<(), Self::Error>
+    where
+        Self: Sized,
+    {
+        for c in self.buf.iter_mut() {
+            *c = color;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ReadPixels for Tile<'a> {
+    fn read_area(
+        &mut self,
+        top_left: Point,
+        size: Size,
+        buf: &mut [Rgb565],
+    ) -> Result<(), Self::Error> {
+        let points = area_points(top_left, size);
+        for (c, p) in buf.iter_mut().zip(points) {
+            *c = self.index(p).map(|i| self.buf[i]).unwrap_or(Rgb565::BLACK);
+        }
+        Ok(())
+    }
+
+    fn write_area(
+        &mut self,
+        top_left: Point,
+        size: Size,
+        buf: &[Rgb565],
+    ) -> Result<(), Self::Error> {
+        let points = area_points(top_left, size);
+        for (c, p) in buf.iter().zip(points) {
+            self.draw_pixel(Pixel(p, *c))?;
+        }
+        Ok(())
+    }
+}
+
+fn area_points(top_left: Point, size: Size) -> impl Iterator<Item = Point> {
+    let width = size.width.max(1);
+    (0..size.width * size.height)
+        .map(move |i| top_left + Point::new((i % width) as i32, (i / width) as i32))
+}
+
+/// Draws with alpha on top of whatever the target already shows
+pub struct Compositor<'a, T> {
+    target: &'a mut T,
+}
+
+impl<'a, T> Compositor<'a, T>
+where
+    T: ReadPixels,
+{
+    pub fn new(target: &'a mut T) -> Self {
+        Compositor { target }
+    }
+
+    pub fn target(&mut self) -> &mut T {
+        self.target
+    }
+
+    /// Blends a single pixel, one read and one write
+    pub fn blend_pixel(&mut self, p: Point, color: Rgb565, alpha: u8) -> Result<(), T::Error> {
+        let Size { width, height } = self.target.size();
+        if alpha == 0 || p.x < 0 || p.y < 0 || p.x as u32 >= width || p.y as u32 >= height {
+            return Ok(());
+        }
+        if alpha == 255 {
+            return self.target.draw_pixel(Pixel(p, color));
+        }
+
+        let mut dst = [Rgb565::BLACK];
+        self.target.read_area(p, Size::new(1, 1), &mut dst)?;
+        self.target
+            .draw_pixel(Pixel(p, blend(dst[0], color, alpha)))
+    }
+
+    /// Translucent rectangle
+    pub fn fill_rect(
+        &mut self,
+        top_left: Point,
+        size: Size,
+        color: Rgb565,
+        alpha: u8,
+    ) -> Result<(), T::Error> {
+        self.blend_area(top_left, size, color, |_, _| alpha)
+    }
+
+    /// Blends `color` through a coverage map, one alpha byte per pixel, row by row
+    pub fn blend_map(
+        &mut self,
+        top_left: Point,
+        size: Size,
+        alphas: &[u8],
+        color: Rgb565,
+    ) -> Result<(), T::Error> {
+        let width = size.width as usize;
+        self.blend_area(top_left, size, color, |x, y| {
+            alphas.get(y * width + x).copied().unwrap_or(0)
+        })
+    }
+
+    /// Blends `color` over an area, `alpha(x, y)` is relative to `top_left`.
+    /// Area is clipped to the target, rows go through in chunks of read / blend / write.
+    fn blend_area<F>(
+        &mut self,
+        top_left: Point,
+        size: Size,
+        color: Rgb565,
+        alpha: F,
+    ) -> Result<(), T::Error>
+    where
+        F: Fn(usize, usize) -> u8,
+    {
+        let Size { width, height } = self.target.size();
+        let x0 = top_left.x.max(0);
+        let y0 = top_left.y.max(0);
+        let x1 = (top_left.x + size.width as i32).min(width as i32);
+        let y1 = (top_left.y + size.height as i32).min(height as i32);
+        if x0 >= x1 || y0 >= y1 {
+            return Ok(());
+        }
+
+        let mut buf = [Rgb565::BLACK; CHUNK];
+
+        for xs in (x0..x1).step_by(CHUNK) {
+            let w = ((x1 - xs) as usize).min(CHUNK);
+            let rows = CHUNK / w;
+
+            let mut y = y0;
+            while y < y1 {
+                let h = rows.min((y1 - y) as usize);
+                let alpha_at = |i: usize| {
+                    alpha(
+                        (xs - top_left.x) as usize + i % w,
+                        (y - top_left.y) as usize + i / w,
+                    )
+                };
+
+                // nothing to blend, don't bother with the round trip
+                if (0..w * h).any(|i| alpha_at(i) != 0) {
+                    let area = Size::new(w as u32, h as u32);
+                    let pixels = &mut buf[..w * h];
+                    self.target.read_area(Point::new(xs, y), area, pixels)?;
+                    for (i, c) in pixels.iter_mut().enumerate() {
+                        *c = blend(*c, color, alpha_at(i));
+                    }
+                    self.target.write_area(Point::new(xs, y), area, pixels)?;
+                }
+
+                y += h as i32;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Anti-aliased one pixel wide line, Xiaolin Wu's algorithm in 16.16 fixed point
+    pub fn line(&mut self, from: Point, to: Point, color: Rgb565) -> Result<(), T::Error> {
+        let (mut x0, mut y0, mut x1, mut y1) = (from.x, from.y, to.x, to.y);
+
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        if steep {
+            core::mem::swap(&mut x0, &mut y0);
+            core::mem::swap(&mut x1, &mut y1);
+        }
+        if x0 > x1 {
+            core::mem::swap(&mut x0, &mut x1);
+            core::mem::swap(&mut y0, &mut y1);
+        }
+
+        let dx = x1 - x0;
+        let gradient = if dx == 0 { 0 } else { ((y1 - y0) << 16) / dx };
+
+        let mut y = y0 << 16;
+        for x in x0..=x1 {
+            let yi = y >> 16;
+            // coverage of the pixel below
+            let frac = ((y >> 8) & 0xff) as u8;
+
+            let (a, b) = if steep {
+                (Point::new(yi, x), Point::new(yi + 1, x))
+            } else {
+                (Point::new(x, yi), Point::new(x, yi + 1))
+            };
+            self.blend_pixel(a, color, 255 - frac)?;
+            self.blend_pixel(b, color, frac)?;
+
+            y += gradient;
+        }
+
+        Ok(())
+    }
+
+    /// Anti-aliased one pixel wide circle outline
+    pub fn circle(&mut self, center: Point, radius: u32, color: Rgb565) -> Result<(), T::Error> {
+        let r2 = radius as u64 * radius as u64;
+
+        let mut x = 0i32;
+        loop {
+            // exact y in 8.8 fixed point
+            let y = isqrt((r2 - (x as u64 * x as u64)) << 16) as i32;
+            let yi = y >> 8;
+            if x > yi {
+                break;
+            }
+            let frac = (y & 0xff) as u8;
+
+            self.plot8(center, x, yi, color, 255 - frac)?;
+            self.plot8(center, x, yi + 1, color, frac)?;
+
+            x += 1;
+        }
+
+        Ok(())
+    }
+
+    /// All 8 octant reflections of (x, y), each point once
+    fn plot8(
+        &mut self,
+        c: Point,
+        x: i32,
+        y: i32,
+        color: Rgb565,
+        alpha: u8,
+    ) -> Result<(), T::Error> {
+        let points = [
+            (x, y),
+            (-x, y),
+            (x, -y),
+            (-x, -y),
+            (y, x),
+            (-y, x),
+            (y, -x),
+            (-y, -x),
+        ];
+        for (i, &(px, py)) in points.iter().enumerate() {
+            if !points[..i].contains(&(px, py)) {
+                self.blend_pixel(c + Point::new(px, py), color, alpha)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Anti-aliased text, glyphs of a bitmap font are box filtered down to half size,
+    /// e.g. `Font12x16` comes out as smooth 6x8 text. Fonts up to 24x32.
+    pub fn text<F>(
+        &mut self,
+        text: &str,
+        top_left: Point,
+        color: Rgb565,
+        font: F,
+    ) -> Result<(), T::Error>
+    where
+        F: Font + Copy,
+    {
+        let glyph = F::CHARACTER_SIZE;
+        let w = glyph.width.div_ceil(2) as usize;
+        let h = glyph.height.div_ceil(2) as usize;
+        if w * h > MAX_GLYPH {
+            return Ok(());
+        }
+
+        let advance = (glyph.width + F::CHARACTER_SPACING).div_ceil(2) as i32;
+        let style = TextStyle::new(font, BinaryColor::On);
+        let mut coverage = [0u8; MAX_GLYPH];
+        let mut utf8 = [0u8; 4];
+        let mut x = top_left.x;
+
+        for ch in text.chars() {
+            for c in coverage.iter_mut() {
+                *c = 0;
+            }
+
+            let s = ch.encode_utf8(&mut utf8);
+            for Pixel(p, c) in Text::new(s, Point::zero()).into_styled(style).into_iter() {
+                if c == BinaryColor::On && p.x >= 0 && p.y >= 0 {
+                    // 4 samples per output pixel
+                    let i = (p.y as usize / 2) * w + p.x as usize / 2;
+                    if let Some(a) = coverage.get_mut(i) {
+                        *a = a.saturating_add(64);
+                    }
+                }
+            }
+
+            let size = Size::new(w as u32, h as u32);
+            self.blend_map(Point::new(x, top_left.y), size, &coverage[..w * h], color)?;
+            x += advance;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+//
+// MKS TFT32_L V3.0 board support
+//
+// Takes the device peripherals, runs the clock tree from the 25MHz crystal
+// and hands out what's on the board, ready to use:
+//
+//   LCD (GPIOE data, PC8 CS, PD13 RS, PB14 WR, PD15 RD) with the PD14 backlight
+//   beeper          PA2, TIM2 CH3 PWM
+//   touch           SPI1 (PA5/PA6/PA7), PC9 CS, PC5 PENIRQ
+//   SPI flash       SPI1, PB9 CS
+//   SD card         SPI3 remapped (PC10/PC11/PC12), PD11 CS
+//   serial header   USART1, PA9 TX / PA10 RX
+//   EEPROM          I2C1, PB6 SCL / PB7 SDA, settings (`settings::Store`)
+//   watchdog        IWDG, see `watchdog::Supervisor`
+//
+// The flash and SD chip selects are from the schematic, check them against
+// the board revision at hand. The touch controller owns SPI1, `Xpt2046::release`
+// gives the bus back for the flash.
+//
+//   let board = Board::new(cx.device, &mut core.DCB, &mut core.DWT).unwrap();
+//
+use cortex_m::peripheral::{DCB, DWT};
+
+use embedded_hal::{digital::v2::OutputPin, spi::MODE_0};
+
+use stm32f1xx_hal::{
+    gpio::{Edge, ExtiPin},
+    i2c::{BlockingI2c, Mode},
+    pac::{self, EXTI},
+    prelude::*,
+    pwm::Channel,
+    rcc::Clocks,
+    serial::{Config, Serial},
+    spi::Spi,
+    timer::{Tim2NoRemap, Timer},
+    watchdog::IndependentWatchdog,
+};
+
+use crate::{
+    beeper::Beeper,
+    boot, clocks,
+    delay::DwtDelay,
+    lcd::{Lcd, LcdError},
+    settings::at24c::{self, Variant},
+    touch::{self, Xpt2046},
+    types::*,
+    watchdog::ResetCause,
+};
+
+pub const SERIAL_BAUD: u32 = 115_200;
+
+/// XPT2046 tops out at 2MHz
+pub const TOUCH_SPI_FREQ: u32 = 1_000_000;
+
+/// Slow enough for SD card initialization
+pub const SD_SPI_FREQ: u32 = 400_000;
+
+pub const EEPROM_I2C_FREQ: u32 = 100_000;
+
+/// EEPROM on I2C1, check against the board revision at hand
+pub const EEPROM_VARIANT: Variant = at24c::AT24C16;
+
+pub struct Board {
+    /// What reset the chip, the RCC flags are cleared
+    pub reset_cause: ResetCause,
+    pub clocks: Clocks,
+    /// Counts on the DWT cycle counter, enabled by `new`
+    pub delay: DwtDelay,
+    /// Not initialized yet, `Lcd::init` (e.g. from `idle`)
+    pub lcd: Lcd<DwtDelay>,
+    pub beeper: Beeper<BeeperPwm>,
+    pub touch: Xpt2046<Spi1, TouchCsPin>,
+    /// Falling edge interrupt source, `enable_interrupt(&board.exti)` to get EXTI9_5
+    pub touch_irq: TouchIrqPin,
+    /// Deselected
+    pub flash_cs: FlashCsPin,
+    pub sd: Spi3,
+    /// Deselected
+    pub sd_cs: SdCsPin,
+    pub serial: Serial1,
+    pub eeprom: I2c1,
+    pub exti: EXTI,
+    /// Not started, stops while a debugger halts the core
+    pub iwdg: IndependentWatchdog,
+}
+
+impl Board {
+    /// Sets up clocks and pins, also enables the DWT cycle counter
+    /// (delays and the RTIC CYCCNT monotonic) and relocates the vector table
+    /// when behind the bootloader
+    pub fn new(device: pac::Peripherals, dcb: &mut DCB, dwt: &mut DWT) -> Result<Self, LcdError> {
+        boot::relocate_vector_table();
+        let reset_cause = ResetCause::take();
+
+        let mut flash = device.FLASH.constrain();
+        let mut rcc = device.RCC.constrain();
+        let clocks = clocks::freeze(rcc.cfgr, &mut flash.acr);
+
+        dcb.enable_trace();
+        DWT::unlock();
+        dwt.enable_cycle_counter();
+        let delay = DwtDelay::new(&clocks);
+
+        let mut afio = device.AFIO.constrain(&mut rcc.apb2);
+        let mut gpioa = device.GPIOA.split(&mut rcc.apb2);
+        let mut gpiob = device.GPIOB.split(&mut rcc.apb2);
+        let mut gpioc = device.GPIOC.split(&mut rcc.apb2);
+        let mut gpiod = device.GPIOD.split(&mut rcc.apb2);
+
+        let lcd = Lcd::new(
+            delay,
+            device.GPIOE,
+            &mut rcc.apb2,
+            gpiod.pd14.into_push_pull_output(&mut gpiod.crh),
+            gpioc.pc8.into_push_pull_output(&mut gpioc.crh),
+            gpiod.pd13.into_push_pull_output(&mut gpiod.crh),
+            gpiob.pb14.into_push_pull_output(&mut gpiob.crh),
+            gpiod.pd15.into_push_pull_output(&mut gpiod.crh),
+        )?;
+
+        let beeper_pin: BeeperPin = gpioa.pa2.into_alternate_push_pull(&mut gpioa.crl);
+        let pwm = Timer::tim2(device.TIM2, &clocks, &mut rcc.apb1).pwm::<Tim2NoRemap, _, _, _>(
+            beeper_pin,
+            &mut afio.mapr,
+            1.khz(),
+        );
+        let beeper = Beeper::new(pwm, Channel::C3);
+
+        let spi1 = Spi::spi1(
+            device.SPI1,
+            (
+                gpioa.pa5.into_alternate_push_pull(&mut gpioa.crl),
+                gpioa.pa6.into_floating_input(&mut gpioa.crl),
+                gpioa.pa7.into_alternate_push_pull(&mut gpioa.crl),
+            ),
+            &mut afio.mapr,
+            MODE_0,
+            TOUCH_SPI_FREQ.hz(),
+            clocks,
+            &mut rcc.apb2,
+        );
+        let touch = Xpt2046::new(
+            spi1,
+            gpioc.pc9.into_push_pull_output(&mut gpioc.crh),
+            touch::Config::default(),
+        );
+
+        let mut touch_irq = gpioc.pc5.into_pull_up_input(&mut gpioc.crl);
+        touch_irq.make_interrupt_source(&mut afio);
+        touch_irq.trigger_on_edge(&device.EXTI, Edge::FALLING);
+
+        let mut flash_cs = gpiob.pb9.into_push_pull_output(&mut gpiob.crh);
+        flash_cs.set_high().ok();
+
+        let sd = Spi::spi3(
+            device.SPI3,
+            (
+                gpioc.pc10.into_alternate_push_pull(&mut gpioc.crh),
+                gpioc.pc11.into_floating_input(&mut gpioc.crh),
+                gpioc.pc12.into_alternate_push_pull(&mut gpioc.crh),
+            ),
+            &mut afio.mapr,
+            MODE_0,
+            SD_SPI_FREQ.hz(),
+            clocks,
+            &mut rcc.apb1,
+        );
+        let mut sd_cs = gpiod.pd11.into_push_pull_output(&mut gpiod.crh);
+        sd_cs.set_high().ok();
+
+        let serial = Serial::usart1(
+            device.USART1,
+            (
+                gpioa.pa9.into_alternate_push_pull(&mut gpioa.crh),
+                gpioa.pa10,
+            ),
+            &mut afio.mapr,
+            Config::default().baudrate(SERIAL_BAUD.bps()),
+            clocks,
+            &mut rcc.apb2,
+        );
+
+        let eeprom = BlockingI2c::i2c1(
+            device.I2C1,
+            (
+                gpiob.pb6.into_alternate_open_drain(&mut gpiob.crl),
+                gpiob.pb7.into_alternate_open_drain(&mut gpiob.crl),
+            ),
+            &mut afio.mapr,
+            Mode::Standard {
+                frequency: EEPROM_I2C_FREQ.hz(),
+            },
+            clocks,
+            &mut rcc.apb1,
+            1000,
+            10,
+            1000,
+            1000,
+        );
+
+        let iwdg = IndependentWatchdog::new(device.IWDG);
+        iwdg.stop_on_debug(&device.DBGMCU, true);
+
+        Ok(Board {
+            reset_cause,
+            clocks,
+            delay,
+            lcd,
+            beeper,
+            touch,
+            touch_irq,
+            flash_cs,
+            sd,
+            sd_cs,
+            serial,
+            eeprom,
+            exti: device.EXTI,
+            iwdg,
+        })
+    }
+}
+
+/// Moves `serial` from `SERIAL_BAUD` to `baud` (e.g. from the settings), before
+/// any transfer. False, and left alone, when PCLK2 can't divide down to it.
+pub fn set_serial_baud(_serial: &mut Serial1, clocks: &Clocks, baud: u32) -> bool {
+    let brr = clocks.pclk2().0 / baud.max(1);
+    if !(16..=0xffff).contains(&brr) {
+        return false;
+    }
+    // the HAL sets the divider only when building the `Serial`, which owns USART1
+    let usart = unsafe { &*pac::USART1::ptr() };
+    usart.cr1.modify(|_, w| w.ue().clear_bit());
+    usart.brr.write(|w| unsafe { w.bits(brr) });
+    usart.cr1.modify(|_, w| w.ue().set_bit());
+    true
+}
@@ -0,0 +1,34 @@
+//
+// Where the application sits in flash
+//
+// Stock boards run the Makerbase bootloader first: it looks for a firmware
+// file on the SD card, decrypts it into flash behind itself and jumps there.
+// Built with the `mks-bootloader` feature the application is linked at that
+// offset (memory/mks.x, keep the two in sync) and `make mks` produces the file
+// (tools/mks_fw.py).
+//
+// The vector table moves with the application, the bootloader doesn't
+// point VTOR at it, `relocate_vector_table` has to before interrupts are
+// enabled (`Board::new` does).
+//
+use cortex_m::peripheral::SCB;
+
+/// Flash taken by the bootloader
+#[cfg(feature = "mks-bootloader")]
+pub const APP_OFFSET: u32 = 0x7000;
+#[cfg(not(feature = "mks-bootloader"))]
+pub const APP_OFFSET: u32 = 0;
+
+pub const FLASH_ORIGIN: u32 = 0x0800_0000;
+
+/// Start of the application and its vector table
+pub const APP_ORIGIN: u32 = FLASH_ORIGIN + APP_OFFSET;
+
+/// Points VTOR at our vector table, harmless without the bootloader
+pub fn relocate_vector_table() {
+    // VTOR wants the table (16 + 68 vectors here) aligned to a power of two past its size
+    const _: [(); 1] = [(); APP_OFFSET.is_multiple_of(512) as usize];
+
+    // written once, before the interrupts it affects are enabled
+    unsafe { (*SCB::PTR).vtor.write(APP_ORIGIN) }
+}
@@ -0,0 +1,79 @@
+//
+// Clock tree for the 25MHz crystal on the board
+//
+// `stm32f1xx_hal` 0.7 feeds HSE straight into the main PLL, which can't make
+// 72MHz out of 25MHz (`use_hse(25.mhz())` fails in `freeze`). The connectivity
+// line has a second PLL in front of it:
+//
+//   HSE 25MHz / PREDIV2 5 = 5MHz * PLL2MUL 8 = PLL2 40MHz / PREDIV1 5 = 8MHz
+//   8MHz * PLLMUL 9 = SYSCLK 72MHz
+//
+// PLL2 and PREDIV1 are set up here, the HAL then sees an 8MHz "HSE" and does
+// the rest (main PLL, flash wait states, bus prescalers), so its `Clocks`
+// (and everything built from them: USART baud rates, SPI, PWM) are right.
+//
+// https://www.st.com/resource/en/reference_manual/cd00171190.pdf, 8.3.12 RCC_CFGR2
+//
+use stm32f1xx_hal::{
+    flash::ACR,
+    pac::RCC,
+    rcc::{Clocks, CFGR},
+    time::{Hertz, U32Ext},
+};
+
+use crate::consts::SYS_FREQ;
+
+/// Crystal on the board
+pub const HSE_FREQ: Hertz = Hertz(25_000_000);
+
+/// What PREDIV1 hands the main PLL, the HAL's idea of HSE
+pub const PLL_INPUT_FREQ: Hertz = Hertz(8_000_000);
+
+/// PREDIV1 divides by 5 (value is n - 1)
+const CFGR2_PREDIV1_DIV5: u32 = 4;
+/// PREDIV2 divides by 5
+const CFGR2_PREDIV2_DIV5: u32 = 4 << 4;
+/// PLL2 multiplies by 8
+const CFGR2_PLL2MUL_8: u32 = 0b0110 << 8;
+/// PREDIV1 takes PLL2 rather than HSE
+const CFGR2_PREDIV1SRC_PLL2: u32 = 1 << 16;
+
+/// Runs the chip at SYS_FREQ from the 25MHz crystal,
+/// in place of `rcc.cfgr.use_hse(..).sysclk(..).freeze(..)`
+pub fn freeze(cfgr: CFGR, acr: &mut ACR) -> Clocks {
+    pll2();
+
+    let clocks = cfgr
+        .use_hse(PLL_INPUT_FREQ)
+        .sysclk(SYS_FREQ)
+        .pclk1(36.mhz())
+        .freeze(acr);
+
+    // delays count SYS_FREQ cycles
+    assert!(clocks.sysclk().0 == SYS_FREQ.0);
+    clocks
+}
+
+/// HSE through PREDIV2 and PLL2 into PREDIV1
+fn pll2() {
+    // CFGR (`constrain`ed) hands out no access to CFGR2
+    let rcc = unsafe { &*RCC::ptr() };
+
+    // e.g. a bootloader left us running on the PLL: back to HSI,
+    // PLL and PLL2 have to be off while they're configured
+    rcc.cfgr.modify(|_, w| w.sw().hsi());
+    while !rcc.cfgr.read().sws().is_hsi() {}
+    rcc.cr
+        .modify(|_, w| w.pllon().clear_bit().pll2on().clear_bit());
+    while rcc.cr.read().pllrdy().bit_is_set() || rcc.cr.read().pll2rdy().bit_is_set() {}
+
+    rcc.cr.modify(|_, w| w.hseon().set_bit());
+    while rcc.cr.read().hserdy().bit_is_clear() {}
+
+    rcc.cfgr2.write(|w| unsafe {
+        w.bits(CFGR2_PREDIV1_DIV5 | CFGR2_PREDIV2_DIV5 | CFGR2_PLL2MUL_8 | CFGR2_PREDIV1SRC_PLL2)
+    });
+
+    rcc.cr.modify(|_, w| w.pll2on().set_bit());
+    while rcc.cr.read().pll2rdy().bit_is_clear() {}
+}
@@ -1,3 +1,4 @@
 use stm32f1xx_hal::time::Hertz;
 
+/// SYSCLK as set up by `clocks::freeze`, what `delay::AsmDelay` counts cycles of
 pub const SYS_FREQ: Hertz = Hertz(72_000_000);
@@ -1,15 +1,23 @@
 use crate::consts::*;
-use cortex_m::asm;
+use cortex_m::{asm, peripheral::DWT};
 use embedded_hal::blocking::delay::*;
+use stm32f1xx_hal::rcc::Clocks;
 
 const CYCLES_PER_MILLIS: u32 = SYS_FREQ.0 / 1_000;
 const CYCLES_PER_MICROS: u32 = SYS_FREQ.0 / 1_000_000;
 
+/// Longest single wait on the cycle counter, well within its wrap around
+const MAX_WAIT: u32 = 1 << 30;
+
+/// Busy loop counting SYS_FREQ cycles, stretches when interrupted
 pub struct AsmDelay;
 
 impl DelayMs<u16> for AsmDelay {
     fn delay_ms(&mut self, ms: u16) {
-        asm::delay(CYCLES_PER_MILLIS * (ms as u32));
+        // a few seconds of cycles don't fit u32
+        for _ in 0..ms {
+            asm::delay(CYCLES_PER_MILLIS);
+        }
     }
 }
 
@@ -18,3 +26,74 @@ impl DelayUs<u16> for AsmDelay {
         asm::delay(CYCLES_PER_MICROS * (us as u32));
     }
 }
+
+/// Waits on the DWT cycle counter at the frozen SYSCLK, interrupts don't stretch it.
+/// The counter has to be running (`DWT::enable_cycle_counter`, as for the RTIC CYCCNT monotonic).
+#[derive(Debug, Clone, Copy)]
+pub struct DwtDelay {
+    sysclk: u32,
+}
+
+impl DwtDelay {
+    pub fn new(clocks: &Clocks) -> Self {
+        DwtDelay {
+            sysclk: clocks.sysclk().0,
+        }
+    }
+
+    pub fn delay_cycles(&self, cycles: u64) {
+        let mut left = cycles;
+        while left > 0 {
+            let n = left.min(MAX_WAIT as u64) as u32;
+            let start = DWT::cycle_count();
+            while DWT::cycle_count().wrapping_sub(start) < n {}
+            left -= n as u64;
+        }
+    }
+
+    /// At least `ns`, rounded up to whole cycles (14ns at 72MHz) plus the call,
+    /// e.g. for bus setup and hold times
+    pub fn delay_ns(&self, ns: u32) {
+        self.delay_cycles((ns as u64 * self.sysclk as u64).div_ceil(1_000_000_000));
+    }
+
+    fn wait_us(&self, us: u64) {
+        self.delay_cycles(us * self.sysclk as u64 / 1_000_000);
+    }
+}
+
+impl DelayMs<u32> for DwtDelay {
+    fn delay_ms(&mut self, ms: u32) {
+        self.wait_us(ms as u64 * 1_000);
+    }
+}
+
+impl DelayMs<u16> for DwtDelay {
+    fn delay_ms(&mut self, ms: u16) {
+        self.delay_ms(ms as u32);
+    }
+}
+
+impl DelayMs<u8> for DwtDelay {
+    fn delay_ms(&mut self, ms: u8) {
+        self.delay_ms(ms as u32);
+    }
+}
+
+impl DelayUs<u32> for DwtDelay {
+    fn delay_us(&mut self, us: u32) {
+        self.wait_us(us as u64);
+    }
+}
+
+impl DelayUs<u16> for DwtDelay {
+    fn delay_us(&mut self, us: u16) {
+        self.delay_us(us as u32);
+    }
+}
+
+impl DelayUs<u8> for DwtDelay {
+    fn delay_us(&mut self, us: u8) {
+        self.delay_us(us as u32);
+    }
+}
@@ -0,0 +1,188 @@
+//
+// Rgb888 -> Rgb565 reduction with dithering, to avoid banding on gradients and photos.
+//
+// https://en.wikipedia.org/wiki/Ordered_dithering
+// https://en.wikipedia.org/wiki/Floyd%E2%80%93Steinberg_dithering
+//
+use embedded_graphics::{
+    drawable::Pixel,
+    geometry::Size,
+    pixelcolor::{Rgb565, Rgb888},
+    prelude::*,
+    DrawTarget,
+};
+
+/// Widest row error diffusion keeps state for,
+/// pixels further right fall back to ordered dithering.
+const MAX_WIDTH: usize = 320;
+
+const BAYER4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// How Rgb888 sources are reduced to Rgb565
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dither {
+    /// Plain truncation
+    None,
+    /// 4x4 Bayer matrix
+    Ordered,
+    /// Error diffusion, expects pixels in row order
+    FloydSteinberg,
+}
+
+/// Quantizer state, feed it pixels row by row with `pixel`.
+pub struct Ditherer {
+    mode: Dither,
+    y: Option<u32>,
+    /// Error for the next row, shifted by one so x - 1 always exists
+    errors: [[i16; 3]; MAX_WIDTH + 2],
+    /// Error going to the right neighbor
+    carry: [i16; 3],
+    /// Error going to the lower right neighbor, lands in `errors` one pixel later
+    diag: [i16; 3],
+    last_x: Option<u32>,
+}
+
+impl Ditherer {
+    pub fn new(mode: Dither) -> Self {
+        Ditherer {
+            mode,
+            y: None,
+            errors: [[0; 3]; MAX_WIDTH + 2],
+            carry: [0; 3],
+            diag: [0; 3],
+            last_x: None,
+        }
+    }
+
+    pub fn mode(&self) -> Dither {
+        self.mode
+    }
+
+    /// Drops accumulated error, e.g. before a new image
+    pub fn reset(&mut self) {
+        self.y = None;
+        self.errors = [[0; 3]; MAX_WIDTH + 2];
+        self.carry = [0; 3];
+        self.diag = [0; 3];
+        self.last_x = None;
+    }
+
+    /// Quantizes a pixel at (x, y) of the source.
+    /// A change of `y` starts a new row, rows may go either way (e.g. bottom-up BMP).
+    pub fn pixel(&mut self, x: u32, y: u32, color: Rgb888) -> Rgb565 {
+        match self.mode {
+            Dither::None => Rgb565::new(color.r() >> 3, color.g() >> 2, color.b() >> 3),
+            Dither::Ordered => ordered(x, y, color),
+            Dither::FloydSteinberg if (x as usize) < MAX_WIDTH => self.diffuse(x, y, color),
+            Dither::FloydSteinberg => ordered(x, y, color),
+        }
+    }
+
+    fn diffuse(&mut self, x: u32, y: u32, color: Rgb888) -> Rgb565 {
+        if self.y != Some(y) {
+            self.y = Some(y);
+            self.errors[0] = [0; 3];
+            self.carry = [0; 3];
+            self.diag = [0; 3];
+            self.last_x = None;
+        }
+        // a gap in the row, carried error belongs elsewhere
+        if self.last_x.map(|l| l + 1) != Some(x) {
+            self.carry = [0; 3];
+            self.diag = [0; 3];
+        }
+        self.last_x = Some(x);
+
+        let i = x as usize;
+        let src = [color.r(), color.g(), color.b()];
+        let bits = [5, 6, 5];
+        let mut out = [0u8; 3];
+
+        for c in 0..3 {
+            let v = (src[c] as i16 + self.errors[i + 1][c] + self.carry[c]).clamp(0, 255) as u8;
+            let q = v >> (8 - bits[c]);
+            let e = v as i16 - expand(q, bits[c]) as i16;
+            out[c] = q;
+
+            self.errors[i][c] += e * 3 / 16;
+            self.errors[i + 1][c] = self.diag[c] + e * 5 / 16;
+            self.diag[c] = e / 16;
+            self.carry[c] = e * 7 / 16;
+        }
+
+        Rgb565::new(out[0], out[1], out[2])
+    }
+}
+
+fn ordered(x: u32, y: u32, color: Rgb888) -> Rgb565 {
+    // threshold scaled to the quantization step, 8 for red/blue, 4 for green
+    let t = BAYER4[(y & 3) as usize][(x & 3) as usize];
+    Rgb565::new(
+        color.r().saturating_add(t >> 1) >> 3,
+        color.g().saturating_add(t >> 2) >> 2,
+        color.b().saturating_add(t >> 1) >> 3,
+    )
+}
+
+/// n-bit channel value back to 8 bits
+fn expand(q: u8, bits: u32) -> u8 {
+    let v = q << (8 - bits);
+    v | v >> bits
+}
+
+/// Rgb888 DrawTarget, dithers pixels before passing them on to an Rgb565 target.
+pub struct Dithered<'a, T> {
+    target: &'a mut T,
+    ditherer: Ditherer,
+}
+
+impl<'a, T> Dithered<'a, T>
+where
+    T: DrawTarget<Rgb565>,
+{
+    pub fn new(target: &'a mut T, mode: Dither) -> Self {
+        Dithered {
+            target,
+            ditherer: Ditherer::new(mode),
+        }
+    }
+
+    /// Drops accumulated error, e.g. before a new image
+    pub fn reset(&mut self) {
+        self.ditherer.reset();
+    }
+}
+
+impl<'a, T> DrawTarget<Rgb888> for Dithered<'a, T>
+where
+    T: DrawTarget<Rgb565>,
+{
+    type Error = T::Error;
+
+    fn draw_pixel(&mut self, pixel: Pixel<Rgb888>) -> Result<(), Self::Error> {
+        let Pixel(p, color) = pixel;
+        if p.x < 0 || p.y < 0 {
+            return Ok(());
+        }
+
+        let color = self.ditherer.pixel(p.x as u32, p.y as u32, color);
+        self.target.draw_pixel(Pixel(p, color))
+    }
+
+    /// Passes pixels on as one iterator, so the target can batch them
+    fn draw_iter<I>(&mut self, item: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Rgb888>>,
+    {
+        let Dithered { target, ditherer } = self;
+        target.draw_iter(
+            item.into_iter()
+                .filter(|Pixel(p, _)| p.x >= 0 && p.y >= 0)
+                .map(|Pixel(p, color)| Pixel(p, ditherer.pixel(p.x as u32, p.y as u32, color))),
+        )
+    }
+
+    fn size(&self) -> Size {
+        self.target.size()
+    }
+}
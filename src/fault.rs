@@ -0,0 +1,450 @@
+//
+// Panic and HardFault handlers that leave a clue on the screen
+//
+// Both take the LCD over from whoever owned it (pins set up again from the
+// stolen peripherals, as `Board` does), draw the message and the fault
+// registers in a red box, beep and halt. Interrupts stay off from then on.
+//
+// The report is also kept in `.uninit` RAM, which a reset (button, watchdog)
+// leaves alone, for the next boot to show:
+//
+//   if let Some(report) = fault::take_last() {
+//       fault::draw(lcd, &report).unwrap();
+//   }
+//
+// https://developer.arm.com/documentation/dui0552/a/cortex-m3-peripherals/system-control-block
+//
+use core::{
+    fmt::{self, Write},
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use cortex_m::{asm, interrupt, peripheral::SCB};
+use cortex_m_rt::{exception, ExceptionFrame};
+
+use embedded_hal::{blocking::delay::DelayUs, digital::v2::OutputPin};
+
+use stm32f1xx_hal::{
+    gpio::{gpioa::PA2, Output, PushPull},
+    pac,
+    prelude::*,
+};
+
+use embedded_graphics::{
+    fonts::{Font6x8, Text},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::Rectangle,
+    style::{PrimitiveStyleBuilder, TextStyle},
+};
+
+use crate::{
+    delay::AsmDelay,
+    lcd::{Lcd, LcdError},
+};
+
+/// Message bytes kept, longer ones are cut
+pub const MESSAGE_LEN: usize = 160;
+
+/// Characters per line in the box
+const COLUMNS: usize = 36;
+
+const MARGIN: i32 = 8;
+const LINE_HEIGHT: i32 = 10;
+
+/// "FLT1", a report was saved
+const MAGIC: u32 = 0x464c_5431;
+
+/// Beep while reporting
+const BEEP_FREQ: u32 = 2_000;
+const BEEP_MS: u32 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Panic,
+    HardFault,
+}
+
+/// System control block fault status and address registers,
+/// plus where the fault happened (HardFault only)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Registers {
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    /// Stacked PC, the faulting instruction (or the one after it)
+    pub pc: u32,
+    /// Stacked LR
+    pub lr: u32,
+}
+
+impl Registers {
+    fn read() -> Self {
+        let scb = unsafe { &*SCB::PTR };
+        Registers {
+            cfsr: scb.cfsr.read(),
+            hfsr: scb.hfsr.read(),
+            mmfar: scb.mmfar.read(),
+            bfar: scb.bfar.read(),
+            pc: 0,
+            lr: 0,
+        }
+    }
+
+    /// The first cause CFSR/HFSR name, roughly
+    pub fn cause(&self) -> &'static str {
+        const CAUSES: &[(u32, &str)] = &[
+            (1 << 25, "divide by zero"),
+            (1 << 24, "unaligned access"),
+            (1 << 19, "no coprocessor"),
+            (1 << 18, "invalid EXC_RETURN"),
+            (1 << 17, "invalid state"),
+            (1 << 16, "undefined instruction"),
+            (1 << 12, "bus fault on stacking"),
+            (1 << 11, "bus fault on unstacking"),
+            (1 << 10, "imprecise bus fault"),
+            (1 << 9, "bus fault"),
+            (1 << 8, "instruction bus fault"),
+            (1 << 4, "MPU fault on stacking"),
+            (1 << 3, "MPU fault on unstacking"),
+            (1 << 1, "data access violation"),
+            (1 << 0, "instruction access violation"),
+        ];
+        if self.hfsr & (1 << 1) != 0 {
+            return "vector table read";
+        }
+        CAUSES
+            .iter()
+            .find(|(bit, _)| self.cfsr & bit != 0)
+            .map_or("unknown", |(_, cause)| cause)
+    }
+
+    /// MMFAR holds the address (MMARVALID)
+    pub fn mmfar_valid(&self) -> bool {
+        self.cfsr & (1 << 7) != 0
+    }
+
+    /// BFAR holds the address (BFARVALID)
+    pub fn bfar_valid(&self) -> bool {
+        self.cfsr & (1 << 15) != 0
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Report {
+    pub kind: Kind,
+    message: [u8; MESSAGE_LEN],
+    len: usize,
+    pub registers: Registers,
+}
+
+impl Report {
+    pub fn new(kind: Kind, registers: Registers) -> Self {
+        Report {
+            kind,
+            message: [0; MESSAGE_LEN],
+            len: 0,
+            registers,
+        }
+    }
+
+    /// What happened, for panics with the file and line
+    pub fn message(&self) -> &str {
+        core::str::from_utf8(&self.message[..self.len]).unwrap_or("?")
+    }
+}
+
+impl Write for Report {
+    /// Cuts, at a char boundary, what doesn't fit
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut n = s.len().min(MESSAGE_LEN - self.len);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.message[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Report")
+            .field("kind", &self.kind)
+            .field("message", &self.message())
+            .field("registers", &self.registers)
+            .finish()
+    }
+}
+
+/// Kept across resets, all plain words: after power up it's garbage,
+/// the magic and the checksum tell
+#[repr(C)]
+struct Saved {
+    magic: u32,
+    kind: u32,
+    len: u32,
+    message: [u8; MESSAGE_LEN],
+    registers: [u32; 6],
+    check: u32,
+}
+
+impl Saved {
+    fn checksum(&self) -> u32 {
+        let head = [self.magic, self.kind, self.len];
+        let words = head.iter().chain(self.registers.iter());
+        let sum = words.fold(0u32, |s, w| s.rotate_left(5) ^ w);
+        self.message
+            .iter()
+            .fold(sum, |s, b| s.rotate_left(5) ^ *b as u32)
+    }
+}
+
+#[link_section = ".uninit.FAULT"]
+static mut SAVED: MaybeUninit<Saved> = MaybeUninit::uninit();
+
+/// Raw, references to a `static mut` are out
+fn saved_ptr() -> *mut Saved {
+    ptr::addr_of_mut!(SAVED).cast()
+}
+
+/// One report at a time, a fault while reporting just halts
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+fn save(report: &Report) {
+    let r = &report.registers;
+    let mut saved = Saved {
+        magic: MAGIC,
+        kind: report.kind as u32,
+        len: report.len as u32,
+        message: report.message,
+        registers: [r.cfsr, r.hfsr, r.mmfar, r.bfar, r.pc, r.lr],
+        check: 0,
+    };
+    saved.check = saved.checksum();
+    unsafe { ptr::write_volatile(saved_ptr(), saved) }
+}
+
+/// The report saved before the last reset, once
+pub fn take_last() -> Option<Report> {
+    let saved = unsafe { ptr::read_volatile(saved_ptr()) };
+    if saved.magic != MAGIC || saved.check != saved.checksum() {
+        return None;
+    }
+    unsafe { ptr::write_volatile(ptr::addr_of_mut!((*saved_ptr()).magic), 0) }
+
+    let kind = match saved.kind {
+        0 => Kind::Panic,
+        1 => Kind::HardFault,
+        _ => return None,
+    };
+    let [cfsr, hfsr, mmfar, bfar, pc, lr] = saved.registers;
+    let mut report = Report::new(
+        kind,
+        Registers {
+            cfsr,
+            hfsr,
+            mmfar,
+            bfar,
+            pc,
+            lr,
+        },
+    );
+    report.len = (saved.len as usize).min(MESSAGE_LEN);
+    report.message = saved.message;
+    Some(report)
+}
+
+/// Red box at the top of the screen with the report in it
+pub fn draw<D>(target: &mut D, report: &Report) -> Result<(), D::Error>
+where
+    D: DrawTarget<Rgb565>,
+{
+    let width = target.size().width as i32;
+    let style = TextStyle::new(Font6x8, Rgb565::WHITE);
+
+    let lines = wrap(report.message()).count();
+    let height = (lines as i32 + 5) * LINE_HEIGHT + 2 * MARGIN;
+
+    Rectangle::new(Point::zero(), Point::new(width - 1, height))
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(Rgb565::RED)
+                .stroke_color(Rgb565::WHITE)
+                .stroke_width(2)
+                .build(),
+        )
+        .draw(target)?;
+
+    let mut y = MARGIN;
+    let mut line = |target: &mut D, text: &str| {
+        let drawn = Text::new(text, Point::new(MARGIN, y))
+            .into_styled(style)
+            .draw(target);
+        y += LINE_HEIGHT;
+        drawn
+    };
+
+    line(
+        target,
+        match report.kind {
+            Kind::Panic => "PANIC",
+            Kind::HardFault => "HARD FAULT",
+        },
+    )?;
+
+    for text in wrap(report.message()) {
+        line(target, text)?;
+    }
+
+    let r = &report.registers;
+    let mut text = Row::new();
+    write!(text, "CFSR  {:08X}  HFSR {:08X}", r.cfsr, r.hfsr).ok();
+    line(target, text.as_str())?;
+
+    let mut text = Row::new();
+    write!(text, "MMFAR {:08X}", r.mmfar).ok();
+    if !r.mmfar_valid() {
+        text.write_str(" (invalid)").ok();
+    }
+    line(target, text.as_str())?;
+
+    let mut text = Row::new();
+    write!(text, "BFAR  {:08X}", r.bfar).ok();
+    if !r.bfar_valid() {
+        text.write_str(" (invalid)").ok();
+    }
+    line(target, text.as_str())?;
+
+    if report.kind == Kind::HardFault {
+        let mut text = Row::new();
+        write!(text, "PC    {:08X}  LR   {:08X}", r.pc, r.lr).ok();
+        line(target, text.as_str())?;
+    }
+    Ok(())
+}
+
+/// Lines of at most COLUMNS bytes, broken at newlines too
+fn wrap(text: &str) -> impl Iterator<Item = &str> {
+    text.split('\n').flat_map(|mut rest| {
+        core::iter::from_fn(move || {
+            if rest.is_empty() {
+                return None;
+            }
+            let mut n = rest.len().min(COLUMNS);
+            while !rest.is_char_boundary(n) {
+                n -= 1;
+            }
+            let (line, tail) = rest.split_at(n);
+            rest = tail;
+            Some(line)
+        })
+    })
+}
+
+/// One line of text on the stack
+struct Row {
+    buf: [u8; COLUMNS + 4],
+    len: usize,
+}
+
+impl Row {
+    fn new() -> Self {
+        Row {
+            buf: [0; COLUMNS + 4],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Row {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// The LCD and the beeper pin as `Board` sets them up, from whoever owned them.
+/// Delays count cycles, interrupts are off.
+unsafe fn take_over() -> Result<(Lcd<AsmDelay>, PA2<Output<PushPull>>), LcdError> {
+    let device = pac::Peripherals::steal();
+    let mut rcc = device.RCC.constrain();
+    let mut gpioa = device.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = device.GPIOB.split(&mut rcc.apb2);
+    let mut gpioc = device.GPIOC.split(&mut rcc.apb2);
+    let mut gpiod = device.GPIOD.split(&mut rcc.apb2);
+
+    let mut lcd = Lcd::new(
+        AsmDelay,
+        device.GPIOE,
+        &mut rcc.apb2,
+        gpiod.pd14.into_push_pull_output(&mut gpiod.crh),
+        gpioc.pc8.into_push_pull_output(&mut gpioc.crh),
+        gpiod.pd13.into_push_pull_output(&mut gpiod.crh),
+        gpiob.pb14.into_push_pull_output(&mut gpiob.crh),
+        gpiod.pd15.into_push_pull_output(&mut gpiod.crh),
+    )?;
+    lcd.recover()?;
+
+    Ok((lcd, gpioa.pa2.into_push_pull_output(&mut gpioa.crl)))
+}
+
+/// Square wave on the piezo, the timer may be in any state
+fn beep(pin: &mut PA2<Output<PushPull>>) {
+    let half_period = (500_000 / BEEP_FREQ) as u16;
+    for _ in 0..BEEP_MS * BEEP_FREQ / 1_000 {
+        pin.set_high().ok();
+        AsmDelay.delay_us(half_period);
+        pin.set_low().ok();
+        AsmDelay.delay_us(half_period);
+    }
+}
+
+fn halt() -> ! {
+    loop {
+        asm::wfi();
+    }
+}
+
+/// Saves, shows and beeps the report, never returns
+pub fn report(report: &Report) -> ! {
+    interrupt::disable();
+    if REPORTING.swap(true, Ordering::Relaxed) {
+        halt();
+    }
+
+    save(report);
+    if let Ok((mut lcd, mut beeper)) = unsafe { take_over() } {
+        draw(&mut lcd, report).ok();
+        beep(&mut beeper);
+    }
+    halt()
+}
+
+#[cfg(not(any(doc, test)))]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    let mut report = Report::new(Kind::Panic, Registers::read());
+    write!(report, "{}", info).ok();
+    self::report(&report)
+}
+
+#[exception]
+fn HardFault(frame: &ExceptionFrame) -> ! {
+    let mut registers = Registers::read();
+    registers.pc = frame.pc;
+    registers.lr = frame.lr;
+
+    let mut report = Report::new(Kind::HardFault, registers);
+    report.write_str(registers.cause()).ok();
+    self::report(&report)
+}
@@ -0,0 +1,375 @@
+//
+// Proportional bitmap fonts with Unicode coverage, meant to live in flash (include_bytes!).
+// Produced from BDF or TTF fonts by tools/mkfont.py, one file per font size.
+//
+// Layout, all numbers little endian:
+//
+//   0  b"FN"
+//   2  version, u8 (1)
+//   3  line height, u8
+//   4  ascent (baseline from the top of the line), u8
+//   5  reserved, u8
+//   6  glyph count, u16
+//   8  kerning pair count, u16
+//  10  bitmap length, u32
+//  14  glyphs, sorted by code point, 14 bytes each:
+//        code point u32, bitmap offset u32,
+//        width u8, height u8, left i8 (from the pen),
+//        top i8 (bitmap top row above the baseline), advance u8, reserved u8
+//  ..  kerning pairs, sorted by (left, right), 9 bytes each:
+//        left code point u32, right code point u32, adjustment i8
+//  ..  bitmaps, 1 bit per pixel, MSB first, rows padded to a byte
+//
+// Text is drawn a line at a time: every row of the line is composed from the
+// glyph rows into a bit buffer, so kerned and overhanging glyphs overlap properly,
+// and goes out through a single LCD window.
+//
+use embedded_graphics::{
+    drawable::Pixel,
+    geometry::{Point, Size},
+    pixelcolor::Rgb565,
+    DrawTarget,
+};
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use crate::lcd::{Lcd, LcdError};
+
+const MAGIC: &[u8] = b"FN";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 14;
+const GLYPH_LEN: usize = 14;
+const KERNING_LEN: usize = 9;
+
+/// Widest visible part of a line, the longer side of the screen
+const MAX_LINE: usize = 320;
+
+/// Drawn for characters missing from the font, first one found
+const REPLACEMENT: [char; 2] = ['\u{FFFD}', '?'];
+
+#[derive(Debug, Clone, Copy)]
+pub enum FontError {
+    BadMagic,
+    Truncated,
+    Unsupported,
+}
+
+/// Parsed font, borrows the encoded bytes
+#[derive(Debug, Clone, Copy)]
+pub struct Font<'a> {
+    line_height: u8,
+    ascent: u8,
+    glyphs: &'a [u8],
+    kerning: &'a [u8],
+    bitmaps: &'a [u8],
+}
+
+/// Bitmap and metrics of one character
+#[derive(Debug, Clone, Copy)]
+pub struct Glyph<'a> {
+    pub width: u8,
+    pub height: u8,
+    /// Bitmap left column relative to the pen
+    pub left: i8,
+    /// Bitmap top row above the baseline
+    pub top: i8,
+    /// Pen movement after the glyph
+    pub advance: u8,
+    bitmap: &'a [u8],
+}
+
+impl<'a> Glyph<'a> {
+    fn stride(&self) -> usize {
+        (self.width as usize).div_ceil(8)
+    }
+
+    /// Packed bits of a bitmap row, MSB is the leftmost pixel
+    pub fn row(&self, y: u32) -> &'a [u8] {
+        let stride = self.stride();
+        let start = y as usize * stride;
+        self.bitmap.get(start..start + stride).unwrap_or(&[])
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> bool {
+        if x >= self.width as u32 {
+            return false;
+        }
+        self.row(y)
+            .get(x as usize / 8)
+            .is_some_and(|b| b & (0x80 >> (x % 8)) != 0)
+    }
+}
+
+impl<'a> Font<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, FontError> {
+        if data.len() < HEADER_LEN {
+            return Err(FontError::Truncated);
+        }
+        if &data[0..2] != MAGIC {
+            return Err(FontError::BadMagic);
+        }
+        if data[2] != VERSION {
+            return Err(FontError::Unsupported);
+        }
+
+        let glyph_count = u16::from_le_bytes([data[6], data[7]]) as usize;
+        let kerning_count = u16::from_le_bytes([data[8], data[9]]) as usize;
+        let bitmap_len = u32::from_le_bytes([data[10], data[11], data[12], data[13]]) as usize;
+
+        let kerning_start = HEADER_LEN + glyph_count * GLYPH_LEN;
+        let bitmap_start = kerning_start + kerning_count * KERNING_LEN;
+        if data.len() < bitmap_start + bitmap_len {
+            return Err(FontError::Truncated);
+        }
+
+        Ok(Font {
+            line_height: data[3],
+            ascent: data[4],
+            glyphs: &data[HEADER_LEN..kerning_start],
+            kerning: &data[kerning_start..bitmap_start],
+            bitmaps: &data[bitmap_start..bitmap_start + bitmap_len],
+        })
+    }
+
+    pub fn line_height(&self) -> u32 {
+        self.line_height as u32
+    }
+
+    /// Baseline distance from the top of a line
+    pub fn ascent(&self) -> u32 {
+        self.ascent as u32
+    }
+
+    /// Glyph of `c`, binary search over the glyph table
+    pub fn glyph(&self, c: char) -> Option<Glyph<'a>> {
+        let count = self.glyphs.len() / GLYPH_LEN;
+        let i = binary_search(count, |i| {
+            read_u32(self.glyphs, i * GLYPH_LEN).cmp(&(c as u32))
+        })?;
+
+        let e = &self.glyphs[i * GLYPH_LEN..(i + 1) * GLYPH_LEN];
+        let offset = read_u32(e, 4) as usize;
+        let (width, height) = (e[8], e[9]);
+        let len = (width as usize).div_ceil(8) * height as usize;
+
+        Some(Glyph {
+            width,
+            height,
+            left: e[10] as i8,
+            top: e[11] as i8,
+            advance: e[12],
+            bitmap: self.bitmaps.get(offset..offset + len)?,
+        })
+    }
+
+    /// Glyph of `c`, or of a replacement character when the font lacks it
+    pub fn glyph_or_replacement(&self, c: char) -> Option<Glyph<'a>> {
+        self.glyph(c)
+            .or_else(|| REPLACEMENT.iter().filter_map(|r| self.glyph(*r)).next())
+    }
+
+    /// Pen adjustment between two neighbors
+    pub fn kerning(&self, left: char, right: char) -> i32 {
+        if self.kerning.is_empty() {
+            return 0;
+        }
+
+        let count = self.kerning.len() / KERNING_LEN;
+        let key = (left as u32, right as u32);
+        binary_search(count, |i| {
+            let o = i * KERNING_LEN;
+            (read_u32(self.kerning, o), read_u32(self.kerning, o + 4)).cmp(&key)
+        })
+        .map_or(0, |i| self.kerning[i * KERNING_LEN + 8] as i8 as i32)
+    }
+
+    /// Pen movement over the whole text
+    pub fn text_width(&self, text: &str) -> i32 {
+        self.layout(text)
+            .last()
+            .map_or(0, |(x, g)| x + g.advance as i32)
+    }
+
+    /// Pen positions and glyphs, with kerning applied
+    fn layout<'t>(&self, text: &'t str) -> Layout<'a, 't> {
+        Layout {
+            font: *self,
+            chars: text.chars(),
+            prev: None,
+            x: 0,
+        }
+    }
+
+    /// Ink of line row `y` for columns `x0..x0 + bits.len() * 8` of the text
+    fn compose_row(&self, text: &str, y: i32, x0: i32, bits: &mut [u8]) {
+        for b in bits.iter_mut() {
+            *b = 0;
+        }
+        let columns = bits.len() as i32 * 8;
+
+        for (pen, glyph) in self.layout(text) {
+            let row = y - (self.ascent as i32 - glyph.top as i32);
+            let left = pen + glyph.left as i32 - x0;
+            if row < 0
+                || row >= glyph.height as i32
+                || left >= columns
+                || left + glyph.width as i32 <= 0
+            {
+                continue;
+            }
+
+            for x in 0..glyph.width as i32 {
+                let col = left + x;
+                if col >= 0 && col < columns && glyph.pixel(x as u32, row as u32) {
+                    bits[col as usize / 8] |= 0x80 >> (col % 8);
+                }
+            }
+        }
+    }
+
+    /// Draws a line of text with its background, row by row into one LCD window.
+    /// Returns the text width, the part off screen is clipped.
+    pub fn draw<D>(
+        &self,
+        lcd: &mut Lcd<D>,
+        text: &str,
+        top_left: Point,
+        color: Rgb565,
+        background: Rgb565,
+    ) -> Result<i32, LcdError>
+    where
+        D: DelayMs<u16> + DelayUs<u16>,
+    {
+        let width = self.text_width(text);
+        let Size {
+            width: screen_w,
+            height: screen_h,
+        } = lcd.size();
+
+        // visible part of the line
+        let x0 = top_left.x.max(0);
+        let y0 = top_left.y.max(0);
+        let x1 = (top_left.x + width).min(screen_w as i32);
+        let y1 = (top_left.y + self.line_height as i32).min(screen_h as i32);
+        if x0 >= x1 || y0 >= y1 {
+            return Ok(width);
+        }
+
+        let size = Size::new((x1 - x0) as u32, (y1 - y0) as u32);
+        let mut bits = [0u8; MAX_LINE / 8];
+        let bits = &mut bits[..(size.width as usize).div_ceil(8)];
+
+        lcd.stream(Point::new(x0, y0), size, |gram| -> Result<(), LcdError> {
+            for y in y0..y1 {
+                self.compose_row(text, y - top_left.y, x0 - top_left.x, bits);
+
+                // ink and background alternate in runs
+                let mut run = (false, 0u32);
+                for x in 0..size.width as usize {
+                    let ink = bits[x / 8] & (0x80 >> (x % 8)) != 0;
+                    if ink != run.0 && run.1 > 0 {
+                        gram.push_run(if run.0 { color } else { background }, run.1)?;
+                        run.1 = 0;
+                    }
+                    run = (ink, run.1 + 1);
+                }
+                gram.push_run(if run.0 { color } else { background }, run.1)?;
+            }
+            Ok(())
+        })?;
+
+        Ok(width)
+    }
+
+    /// Draws ink only, leaving the background alone.
+    /// Works with any target, the LCD gets the pixels in row bursts.
+    pub fn draw_transparent<T>(
+        &self,
+        target: &mut T,
+        text: &str,
+        top_left: Point,
+        color: Rgb565,
+    ) -> Result<i32, T::Error>
+    where
+        T: DrawTarget<Rgb565>,
+    {
+        let width = self.text_width(text);
+        let x0 = top_left.x.max(0);
+        let x1 = (top_left.x + width).min(target.size().width as i32);
+        if x0 >= x1 {
+            return Ok(width);
+        }
+
+        let columns = (x1 - x0) as usize;
+        let mut bits = [0u8; MAX_LINE / 8];
+        let bits = &mut bits[..columns.div_ceil(8)];
+
+        for y in 0..self.line_height as i32 {
+            self.compose_row(text, y, x0 - top_left.x, bits);
+            let bits = &*bits;
+            target.draw_iter(
+                (0..columns)
+                    .filter(|x| bits[x / 8] & (0x80 >> (x % 8)) != 0)
+                    .map(|x| Pixel(Point::new(x0 + x as i32, top_left.y + y), color)),
+            )?;
+        }
+
+        Ok(width)
+    }
+}
+
+struct Layout<'a, 't> {
+    font: Font<'a>,
+    chars: core::str::Chars<'t>,
+    prev: Option<char>,
+    x: i32,
+}
+
+impl<'a, 't> Iterator for Layout<'a, 't> {
+    type Item = (i32, Glyph<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let c = self.chars.next()?;
+            let glyph = match self.font.glyph_or_replacement(c) {
+                Some(g) => g,
+                None => continue,
+            };
+
+            if let Some(prev) = self.prev {
+                self.x += self.font.kerning(prev, c);
+            }
+            self.prev = Some(c);
+
+            let pen = self.x;
+            self.x += glyph.advance as i32;
+            return Some((pen, glyph));
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Index of the entry `cmp` finds equal, entries are sorted
+fn binary_search<F>(count: usize, cmp: F) -> Option<usize>
+where
+    F: Fn(usize) -> core::cmp::Ordering,
+{
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        match cmp(mid) {
+            core::cmp::Ordering::Less => lo = mid + 1,
+            core::cmp::Ordering::Greater => hi = mid,
+            core::cmp::Ordering::Equal => return Some(mid),
+        }
+    }
+    None
+}
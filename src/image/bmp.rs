@@ -0,0 +1,200 @@
+//
+// Windows BMP decoder: uncompressed 16 (565 / 555), 24 and 32 bit,
+// bottom-up and top-down. Decodes row by row into a single LCD window.
+//
+// https://en.wikipedia.org/wiki/BMP_file_format
+//
+use embedded_graphics::{
+    geometry::{Point, Size},
+    pixelcolor::{raw::RawU16, Rgb565, Rgb888},
+};
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use crate::{
+    dither::{Dither, Ditherer},
+    lcd::{Gram, Lcd},
+};
+
+use super::{read_exact, skip, ImageError, Read};
+
+const FILE_HEADER_LEN: usize = 14;
+const INFO_HEADER_LEN: usize = 40;
+const MASKS_LEN: usize = 12;
+
+/// Widest row we keep a buffer for
+const MAX_WIDTH: u32 = 320;
+const MAX_STRIDE: usize = MAX_WIDTH as usize * 4;
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Rgb565,
+    Rgb555,
+    Bgr888,
+    Bgrx8888,
+}
+
+/// BMP headers, pixel data is decoded with `draw`
+#[derive(Debug, Clone, Copy)]
+pub struct Bmp {
+    size: Size,
+    format: Format,
+    bottom_up: bool,
+    stride: usize,
+}
+
+impl Bmp {
+    /// Reads the headers, leaves `src` at the start of pixel data
+    pub fn new<R: Read>(src: &mut R) -> Result<Self, ImageError<R::Error>> {
+        let mut hdr = [0u8; FILE_HEADER_LEN + INFO_HEADER_LEN + MASKS_LEN];
+
+        read_exact(src, &mut hdr[..FILE_HEADER_LEN + INFO_HEADER_LEN])?;
+        if &hdr[0..2] != b"BM" {
+            return Err(ImageError::Format);
+        }
+
+        let data_offset = le32(&hdr[10..]) as usize;
+        let info_len = le32(&hdr[14..]) as usize;
+        // older OS/2 headers are smaller
+        if info_len < INFO_HEADER_LEN {
+            return Err(ImageError::Unsupported);
+        }
+
+        let width = le32(&hdr[18..]) as i32;
+        let height = le32(&hdr[22..]) as i32;
+        let bpp = le16(&hdr[28..]);
+        let compression = le32(&hdr[30..]);
+
+        let mut consumed = FILE_HEADER_LEN + INFO_HEADER_LEN;
+
+        // channel masks follow the basic header with BI_BITFIELDS,
+        // V4/V5 headers keep them at the same place
+        let masks = if compression == BI_BITFIELDS {
+            read_exact(src, &mut hdr[consumed..consumed + MASKS_LEN])?;
+            let m = &hdr[consumed..];
+            consumed += MASKS_LEN;
+            Some((le32(m), le32(&m[4..]), le32(&m[8..])))
+        } else if compression == BI_RGB {
+            None
+        } else {
+            return Err(ImageError::Unsupported);
+        };
+
+        let format = match (bpp, masks) {
+            (16, None) => Format::Rgb555,
+            (16, Some((0xf800, 0x07e0, 0x001f))) => Format::Rgb565,
+            (16, Some((0x7c00, 0x03e0, 0x001f))) => Format::Rgb555,
+            (24, None) => Format::Bgr888,
+            (32, None) | (32, Some((0xff_0000, 0xff00, 0xff))) => Format::Bgrx8888,
+            _ => return Err(ImageError::Unsupported),
+        };
+
+        if width <= 0 || height == 0 || height.unsigned_abs() > 0xffff {
+            return Err(ImageError::Format);
+        }
+        if width as u32 > MAX_WIDTH {
+            return Err(ImageError::Unsupported);
+        }
+
+        if data_offset < consumed {
+            return Err(ImageError::Format);
+        }
+        skip(src, data_offset - consumed)?;
+
+        Ok(Bmp {
+            size: Size::new(width as u32, height.unsigned_abs()),
+            format,
+            bottom_up: height > 0,
+            stride: (width as usize * bpp as usize).div_ceil(32) * 4,
+        })
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Decodes pixel data into a window at `top_left`, image has to fit on the screen.
+    /// `src` has to be where `new` left it.
+    pub fn draw<R, D>(
+        &self,
+        src: &mut R,
+        lcd: &mut Lcd<D>,
+        top_left: Point,
+        dither: Dither,
+    ) -> Result<(), ImageError<R::Error>>
+    where
+        R: Read,
+        D: DelayMs<u16> + DelayUs<u16>,
+    {
+        // bottom-up files are drawn in file order, by flipping the LCD line direction
+        if self.bottom_up {
+            lcd.stream_bottom_up(top_left, self.size, |gram| {
+                self.decode_rows(src, gram, dither)
+            })
+        } else {
+            lcd.stream(top_left, self.size, |gram| {
+                self.decode_rows(src, gram, dither)
+            })
+        }
+    }
+
+    fn decode_rows<R, D>(
+        &self,
+        src: &mut R,
+        gram: &mut Gram<D>,
+        dither: Dither,
+    ) -> Result<(), ImageError<R::Error>>
+    where
+        R: Read,
+        D: DelayMs<u16> + DelayUs<u16>,
+    {
+        let mut buf = [0u8; MAX_STRIDE];
+        let row = &mut buf[..self.stride];
+        let Size { width, height } = self.size;
+        let mut ditherer = Ditherer::new(dither);
+
+        for i in 0..height {
+            read_exact(src, row)?;
+
+            // image row, only matters for the dither pattern
+            let y = if self.bottom_up { height - 1 - i } else { i };
+
+            for x in 0..width {
+                gram.push(self.pixel(row, x, y, &mut ditherer))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn pixel(&self, row: &[u8], x: u32, y: u32, ditherer: &mut Ditherer) -> Rgb565 {
+        let x_us = x as usize;
+        match self.format {
+            Format::Rgb565 => Rgb565::from(RawU16::new(le16(&row[x_us * 2..]))),
+            Format::Rgb555 => {
+                let p = le16(&row[x_us * 2..]);
+                let g = ((p >> 5) & 0x1f) as u8;
+                Rgb565::new((p >> 10) as u8 & 0x1f, g << 1 | g >> 4, p as u8 & 0x1f)
+            }
+            Format::Bgr888 => {
+                let p = &row[x_us * 3..];
+                ditherer.pixel(x, y, Rgb888::new(p[2], p[1], p[0]))
+            }
+            Format::Bgrx8888 => {
+                let p = &row[x_us * 4..];
+                ditherer.pixel(x, y, Rgb888::new(p[2], p[1], p[0]))
+            }
+        }
+    }
+}
+
+fn le16(b: &[u8]) -> u16 {
+    u16::from_le_bytes([b[0], b[1]])
+}
+
+fn le32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
@@ -0,0 +1,70 @@
+//
+// Compact / streaming image formats, decoded straight into LCD windows.
+//
+use core::convert::Infallible;
+
+use crate::lcd::LcdError;
+
+pub mod bmp;
+pub mod qoi;
+pub mod rle;
+
+/// Byte source for streaming decoders, e.g. a file on external storage.
+pub trait Read {
+    type Error;
+
+    /// Reads up to `buf.len()` bytes, 0 means end of data.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+impl Read for &[u8] {
+    type Error = Infallible;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let n = buf.len().min(self.len());
+        buf[..n].copy_from_slice(&self[..n]);
+        *self = &self[n..];
+        Ok(n)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ImageError<E> {
+    Read(E),
+    /// Data ended before the image did
+    Truncated,
+    /// Not a valid file of the expected format
+    Format,
+    /// Valid file, but a variant we don't decode
+    Unsupported,
+    Lcd(LcdError),
+}
+
+impl<E> From<LcdError> for ImageError<E> {
+    fn from(e: LcdError) -> Self {
+        ImageError::Lcd(e)
+    }
+}
+
+/// Fills `buf` completely
+pub(crate) fn read_exact<R: Read>(src: &mut R, buf: &mut [u8]) -> Result<(), ImageError<R::Error>> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match src.read(&mut buf[pos..]).map_err(ImageError::Read)? {
+            0 => return Err(ImageError::Truncated),
+            n => pos += n,
+        }
+    }
+    Ok(())
+}
+
+/// Drops `n` bytes
+pub(crate) fn skip<R: Read>(src: &mut R, mut n: usize) -> Result<(), ImageError<R::Error>> {
+    let mut buf = [0u8; 16];
+    while n > 0 {
+        let chunk = n.min(buf.len());
+        read_exact(src, &mut buf[..chunk])?;
+        n -= chunk;
+    }
+    Ok(())
+}
@@ -0,0 +1,185 @@
+//
+// QOI, "Quite OK Image" format, decoded as a stream into a single LCD window.
+// Alpha is ignored.
+//
+// https://qoiformat.org/qoi-specification.pdf
+//
+use embedded_graphics::{
+    geometry::{Point, Size},
+    pixelcolor::Rgb888,
+};
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use crate::{
+    dither::{Dither, Ditherer},
+    lcd::Lcd,
+};
+
+use super::{read_exact, ImageError, Read};
+
+const HEADER_LEN: usize = 14;
+const MAGIC: &[u8] = b"qoif";
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RGB: u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+const OP_MASK: u8 = 0xc0;
+
+/// QOI header, pixel data is decoded with `draw`
+#[derive(Debug, Clone, Copy)]
+pub struct Qoi {
+    size: Size,
+}
+
+impl Qoi {
+    /// Reads the header, leaves `src` at the start of pixel data
+    pub fn new<R: Read>(src: &mut R) -> Result<Self, ImageError<R::Error>> {
+        let mut hdr = [0u8; HEADER_LEN];
+        read_exact(src, &mut hdr)?;
+
+        if &hdr[0..4] != MAGIC {
+            return Err(ImageError::Format);
+        }
+
+        let width = u32::from_be_bytes([hdr[4], hdr[5], hdr[6], hdr[7]]);
+        let height = u32::from_be_bytes([hdr[8], hdr[9], hdr[10], hdr[11]]);
+        let channels = hdr[12];
+
+        if width == 0 || height == 0 || !(channels == 3 || channels == 4) {
+            return Err(ImageError::Format);
+        }
+        if width > 0xffff || height > 0xffff {
+            return Err(ImageError::Unsupported);
+        }
+
+        Ok(Qoi {
+            size: Size::new(width, height),
+        })
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Decodes pixel data into a window at `top_left`, image has to fit on the screen.
+    /// `src` has to be where `new` left it.
+    pub fn draw<R, D>(
+        &self,
+        src: &mut R,
+        lcd: &mut Lcd<D>,
+        top_left: Point,
+        dither: Dither,
+    ) -> Result<(), ImageError<R::Error>>
+    where
+        R: Read,
+        D: DelayMs<u16> + DelayUs<u16>,
+    {
+        let Size { width, height } = self.size;
+        let total = width * height;
+
+        lcd.stream(top_left, self.size, |gram| {
+            let mut dec = Decoder::new(src);
+            let mut ditherer = Ditherer::new(dither);
+            let mut i = 0;
+
+            while i < total {
+                let ([r, g, b, _], run) = dec.next_pixel()?;
+                let color = Rgb888::new(r, g, b);
+                let n = run.min(total - i);
+
+                if dither == Dither::None {
+                    gram.push_run(ditherer.pixel(0, 0, color), n)?;
+                } else {
+                    for j in i..i + n {
+                        gram.push(ditherer.pixel(j % width, j / width, color))?;
+                    }
+                }
+
+                i += n;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+struct Decoder<'r, R> {
+    src: &'r mut R,
+    buf: [u8; 64],
+    pos: usize,
+    len: usize,
+    index: [[u8; 4]; 64],
+    px: [u8; 4],
+}
+
+impl<'r, R: Read> Decoder<'r, R> {
+    fn new(src: &'r mut R) -> Self {
+        Decoder {
+            src,
+            buf: [0; 64],
+            pos: 0,
+            len: 0,
+            index: [[0; 4]; 64],
+            px: [0, 0, 0, 255],
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, ImageError<R::Error>> {
+        if self.pos == self.len {
+            self.len = self.src.read(&mut self.buf).map_err(ImageError::Read)?;
+            self.pos = 0;
+            if self.len == 0 {
+                return Err(ImageError::Truncated);
+            }
+        }
+
+        let b = self.buf[self.pos];
+        self.pos += 1;
+        Ok(b)
+    }
+
+    /// Next pixel and how many times it repeats
+    fn next_pixel(&mut self) -> Result<([u8; 4], u32), ImageError<R::Error>> {
+        let b = self.byte()?;
+        let mut run = 1;
+
+        match b {
+            OP_RGB => {
+                for c in 0..3 {
+                    self.px[c] = self.byte()?;
+                }
+            }
+            OP_RGBA => {
+                for c in 0..4 {
+                    self.px[c] = self.byte()?;
+                }
+            }
+            _ => match b & OP_MASK {
+                OP_INDEX => self.px = self.index[b as usize],
+                OP_DIFF => {
+                    self.px[0] = self.px[0].wrapping_add((b >> 4 & 3).wrapping_sub(2));
+                    self.px[1] = self.px[1].wrapping_add((b >> 2 & 3).wrapping_sub(2));
+                    self.px[2] = self.px[2].wrapping_add((b & 3).wrapping_sub(2));
+                }
+                OP_LUMA => {
+                    let b2 = self.byte()?;
+                    let dg = (b & 0x3f).wrapping_sub(32);
+                    self.px[0] = self.px[0].wrapping_add(dg.wrapping_add(b2 >> 4).wrapping_sub(8));
+                    self.px[1] = self.px[1].wrapping_add(dg);
+                    self.px[2] = self.px[2].wrapping_add(dg.wrapping_add(b2 & 0xf).wrapping_sub(8));
+                }
+                // OP_RUN
+                _ => run = (b & 0x3f) as u32 + 1,
+            },
+        }
+
+        let [r, g, b, a] = self.px;
+        let hash = (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64;
+        self.index[hash] = self.px;
+
+        Ok((self.px, run))
+    }
+}
@@ -0,0 +1,178 @@
+//
+// Run length encoded Rgb565 images, meant to live in flash (include_bytes!).
+// Produced by tools/png2rle.py
+//
+// Layout, all numbers little endian:
+//
+//   0  b"RL"
+//   2  width, u16
+//   4  height, u16
+//   6  palette length, u16, 0 means colors are stored as raw Rgb565
+//   8  palette, Rgb565 u16 per entry
+//   .. packets, covering the whole image row by row:
+//        0nnnnnnn            run of n+1 pixels, one color follows
+//        10nnnnnn nnnnnnnn   run of n+1 pixels (14 bit n), one color follows
+//        11nnnnnn            n+1 literal colors follow
+//      a color is a palette index byte or a raw Rgb565 u16
+//
+use embedded_graphics::{
+    drawable::Pixel,
+    geometry::{Point, Size},
+    pixelcolor::{raw::RawU16, Rgb565},
+};
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use crate::lcd::{Lcd, LcdError};
+
+const MAGIC: &[u8] = b"RL";
+const HEADER_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub enum RleError {
+    BadMagic,
+    Truncated,
+    Lcd(LcdError),
+}
+
+impl From<LcdError> for RleError {
+    fn from(e: LcdError) -> Self {
+        RleError::Lcd(e)
+    }
+}
+
+/// Parsed RLE image, borrows the encoded bytes
+#[derive(Debug, Clone, Copy)]
+pub struct RleImage<'a> {
+    size: Size,
+    palette: &'a [u8],
+    packets: &'a [u8],
+}
+
+impl<'a> RleImage<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, RleError> {
+        if data.len() < HEADER_LEN {
+            return Err(RleError::Truncated);
+        }
+        if &data[0..2] != MAGIC {
+            return Err(RleError::BadMagic);
+        }
+
+        let width = u16::from_le_bytes([data[2], data[3]]);
+        let height = u16::from_le_bytes([data[4], data[5]]);
+        let palette_len = u16::from_le_bytes([data[6], data[7]]) as usize;
+
+        let packets_start = HEADER_LEN + palette_len * 2;
+        if data.len() < packets_start {
+            return Err(RleError::Truncated);
+        }
+
+        Ok(RleImage {
+            size: Size::new(width as u32, height as u32),
+            palette: &data[HEADER_LEN..packets_start],
+            packets: &data[packets_start..],
+        })
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// (color, count) runs, row by row. Literal packets come out as runs of 1.
+    pub fn runs(&self) -> Runs<'a> {
+        Runs {
+            image: *self,
+            pos: 0,
+            literals: 0,
+        }
+    }
+
+    /// Pixels for a generic DrawTarget, e.g. `target.draw_iter(img.pixels(p))`.
+    pub fn pixels(&self, top_left: Point) -> impl Iterator<Item = Pixel<Rgb565>> + 'a {
+        let width = self.size.width.max(1);
+        let mut i = 0u32;
+        self.runs().flat_map(move |(c, n)| {
+            let start = i;
+            i += n;
+            (start..start + n).map(move |j| {
+                Pixel(
+                    top_left + Point::new((j % width) as i32, (j / width) as i32),
+                    c,
+                )
+            })
+        })
+    }
+
+    /// Draws the whole image as one LCD window transaction.
+    /// Image has to fit on the screen.
+    pub fn draw<D>(&self, lcd: &mut Lcd<D>, top_left: Point) -> Result<(), RleError>
+    where
+        D: DelayMs<u16> + DelayUs<u16>,
+    {
+        lcd.stream(top_left, self.size, |gram| {
+            for (c, n) in self.runs() {
+                gram.push_run(c, n)?;
+            }
+            if gram.remaining() > 0 {
+                Err(RleError::Truncated)
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    fn color(&self, packets_pos: usize) -> Option<(Rgb565, usize)> {
+        if self.palette.is_empty() {
+            let b = self.packets.get(packets_pos..packets_pos + 2)?;
+            let raw = u16::from_le_bytes([b[0], b[1]]);
+            Some((Rgb565::from(RawU16::new(raw)), 2))
+        } else {
+            let i = *self.packets.get(packets_pos)? as usize * 2;
+            let b = self.palette.get(i..i + 2)?;
+            let raw = u16::from_le_bytes([b[0], b[1]]);
+            Some((Rgb565::from(RawU16::new(raw)), 1))
+        }
+    }
+}
+
+/// Iterator over (color, count) runs of an `RleImage`, stops early on malformed data
+pub struct Runs<'a> {
+    image: RleImage<'a>,
+    pos: usize,
+    literals: u32,
+}
+
+impl<'a> Iterator for Runs<'a> {
+    type Item = (Rgb565, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.literals > 0 {
+            let (c, len) = self.image.color(self.pos)?;
+            self.pos += len;
+            self.literals -= 1;
+            return Some((c, 1));
+        }
+
+        let packets = self.image.packets;
+        let b = *packets.get(self.pos)?;
+        match b >> 6 {
+            0b00 | 0b01 => {
+                let (c, len) = self.image.color(self.pos + 1)?;
+                self.pos += 1 + len;
+                Some((c, (b & 0x7f) as u32 + 1))
+            }
+            0b10 => {
+                let lo = *packets.get(self.pos + 1)?;
+                let n = ((b & 0x3f) as u32) << 8 | lo as u32;
+                let (c, len) = self.image.color(self.pos + 2)?;
+                self.pos += 2 + len;
+                Some((c, n + 1))
+            }
+            _ => {
+                self.literals = (b & 0x3f) as u32 + 1;
+                self.pos += 1;
+                self.next()
+            }
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//
+// Indexed color: an off-screen frame of 1/2/4/8 bit palette indexes,
+// resolved to Rgb565 through a palette when flushed to the LCD.
+//
+// A whole 240x320 screen is 38400 bytes at 4bpp or 19200 bytes at 2bpp,
+// recoloring it (themes, blinking) is a palette change and a re-flush.
+//
+use core::convert::Infallible;
+
+use embedded_graphics::{
+    drawable::Pixel,
+    geometry::{Point, Size},
+    pixelcolor::{
+        raw::{RawData, RawU8},
+        PixelColor, Rgb565,
+    },
+    prelude::*,
+    DrawTarget,
+};
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use crate::lcd::{Lcd, LcdError};
+
+/// Palette index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Index(pub u8);
+
+impl PixelColor for Index {
+    type Raw = RawU8;
+}
+
+impl From<RawU8> for Index {
+    fn from(raw: RawU8) -> Self {
+        Index(raw.into_inner())
+    }
+}
+
+/// Bits per pixel of an `IndexedFrame`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bpp {
+    One = 1,
+    Two = 2,
+    Four = 4,
+    Eight = 8,
+}
+
+impl Bpp {
+    fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Largest index that fits
+    pub fn max_index(self) -> u8 {
+        ((1u16 << self.bits()) - 1) as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum IndexedError {
+    /// Buffer is too small for the frame size
+    BufferSize,
+}
+
+/// Up to 256 Rgb565 colors
+#[derive(Clone)]
+pub struct Palette {
+    colors: [Rgb565; 256],
+}
+
+impl Palette {
+    /// All black palette
+    pub fn new() -> Self {
+        Palette {
+            colors: [Rgb565::BLACK; 256],
+        }
+    }
+
+    /// Palette starting with the given colors, rest is black
+    pub fn from_colors(colors: &[Rgb565]) -> Self {
+        let mut palette = Palette::new();
+        for (i, c) in colors.iter().take(256).enumerate() {
+            palette.colors[i] = *c;
+        }
+        palette
+    }
+
+    pub fn get(&self, index: Index) -> Rgb565 {
+        self.colors[index.0 as usize]
+    }
+
+    pub fn set(&mut self, index: Index, color: Rgb565) {
+        self.colors[index.0 as usize] = color;
+    }
+
+    /// Cycles entries in `first..=last` by one, e.g. for animated gradients
+    pub fn rotate(&mut self, first: Index, last: Index) {
+        if first.0 < last.0 {
+            self.colors[first.0 as usize..=last.0 as usize].rotate_right(1);
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new()
+    }
+}
+
+/// Off-screen frame of palette indexes, MSB first packed rows.
+pub struct IndexedFrame<'a> {
+    buf: &'a mut [u8],
+    size: Size,
+    bpp: Bpp,
+    stride: usize,
+    /// Rows touched since the last flush, inclusive
+    dirty: Option<(u32, u32)>,
+}
+
+impl<'a> IndexedFrame<'a> {
+    /// `buf` needs at least `IndexedFrame::buffer_len(size, bpp)` bytes
+    pub fn new(buf: &'a mut [u8], size: Size, bpp: Bpp) -> Result<Self, IndexedError> {
+        if buf.len() < Self::buffer_len(size, bpp) {
+            return Err(IndexedError::BufferSize);
+        }
+
+        Ok(IndexedFrame {
+            buf,
+            size,
+            bpp,
+            stride: Self::stride(size, bpp),
+            dirty: Some((0, size.height.saturating_sub(1))),
+        })
+    }
+
+    pub fn buffer_len(size: Size, bpp: Bpp) -> usize {
+        Self::stride(size, bpp) * size.height as usize
+    }
+
+    fn stride(size: Size, bpp: Bpp) -> usize {
+        (size.width * bpp.bits()).div_ceil(8) as usize
+    }
+
+    pub fn bpp(&self) -> Bpp {
+        self.bpp
+    }
+
+    /// Index at (x, y), 0 outside of the frame
+    pub fn get(&self, x: u32, y: u32) -> Index {
+        if x >= self.size.width || y >= self.size.height {
+            return Index(0);
+        }
+
+        let (i, shift) = self.locate(x, y);
+        Index((self.buf[i] >> shift) & self.bpp.max_index())
+    }
+
+    /// Sets index at (x, y), out of range indexes are masked
+    pub fn set(&mut self, x: u32, y: u32, index: Index) {
+        if x >= self.size.width || y >= self.size.height {
+            return;
+        }
+
+        let (i, shift) = self.locate(x, y);
+        let mask = self.bpp.max_index() << shift;
+        self.buf[i] = (self.buf[i] & !mask) | ((index.0 << shift) & mask);
+        self.mark_dirty(y, y);
+    }
+
+    /// Forces the next `flush_dirty` to redraw everything, e.g. after a palette change
+    pub fn invalidate(&mut self) {
+        self.mark_dirty(0, self.size.height.saturating_sub(1));
+    }
+
+    /// Redraws the whole frame at `top_left`, in one LCD window
+    pub fn flush<D>(
+        &mut self,
+        lcd: &mut Lcd<D>,
+        top_left: Point,
+        palette: &Palette,
+    ) -> Result<(), LcdError>
+    where
+        D: DelayMs<u16> + DelayUs<u16>,
+    {
+        self.dirty = None;
+        self.flush_rows(lcd, top_left, palette, 0, self.size.height)
+    }
+
+    /// Redraws rows changed since the last flush
+    pub fn flush_dirty<D>(
+        &mut self,
+        lcd: &mut Lcd<D>,
+        top_left: Point,
+        palette: &Palette,
+    ) -> Result<(), LcdError>
+    where
+        D: DelayMs<u16> + DelayUs<u16>,
+    {
+        match self.dirty.take() {
+            Some((first, last)) => self.flush_rows(lcd, top_left, palette, first, last + 1),
+            None => Ok(()),
+        }
+    }
+
+    fn flush_rows<D>(
+        &self,
+        lcd: &mut Lcd<D>,
+        top_left: Point,
+        palette: &Palette,
+        from: u32,
+        to: u32,
+    ) -> Result<(), LcdError>
+    where
+        D: DelayMs<u16> + DelayUs<u16>,
+    {
+        let width = self.size.width;
+        let size = Size::new(width, to - from);
+
+        lcd.stream(top_left + Point::new(0, from as i32), size, |gram| {
+            // neighbors of the same color go out as one run
+            let mut run: Option<(Rgb565, u32)> = None;
+
+            for y in from..to {
+                for x in 0..width {
+                    let c = palette.get(self.get(x, y));
+                    run = match run {
+                        Some((rc, n)) if rc == c => Some((rc, n + 1)),
+                        Some((rc, n)) => {
+                            gram.push_run(rc, n)?;
+                            Some((c, 1))
+                        }
+                        None => Some((c, 1)),
+                    };
+                }
+            }
+
+            if let Some((c, n)) = run {
+                gram.push_run(c, n)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Byte index and shift of the pixel
+    fn locate(&self, x: u32, y: u32) -> (usize, u32) {
+        let bits = self.bpp.bits();
+        let bit = x * bits;
+        let i = y as usize * self.stride + (bit / 8) as usize;
+        (i, 8 - bits - bit % 8)
+    }
+
+    fn mark_dirty(&mut self, first: u32, last: u32) {
+        self.dirty = Some(match self.dirty {
+            Some((f, l)) => (f.min(first), l.max(last)),
+            None => (first, last),
+        });
+    }
+}
+
+impl<'a> DrawTarget<Index> for IndexedFrame<'a> {
+    type Error = Infallible;
+
+    fn draw_pixel(&mut self, pixel: Pixel<Index>) -> Result<(), Self::Error> {
+        let Pixel(p, index) = pixel;
+        if p.x >= 0 && p.y >= 0 {
+            self.set(p.x as u32, p.y as u32, index);
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn clear(&mut self, color: Index) -> Result<(), Self::Error>
+    where
+        Self: Sized,
+    {
+        // repeat the index over a whole byte
+        let bits = self.bpp.bits();
+        let index = color.0 & self.bpp.max_index();
+        let mut byte = 0u8;
+        for i in 0..8 / bits {
+            byte |= index << (i * bits);
+        }
+
+        for b in self.buf.iter_mut() {
+            *b = byte;
+        }
+        self.invalidate();
+        Ok(())
+    }
+}
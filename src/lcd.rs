@@ -10,10 +10,10 @@ use stm32f1xx_hal::{gpio::*, rcc::APB2};
 
 use stm32f1xx_hal::pac::GPIOE;
 
-use cortex_m_semihosting::hprintln;
-
 use embedded_hal::digital::v2::OutputPin;
 
+use crate::math::isqrt;
+
 use embedded_hal::blocking::delay::DelayMs;
 use embedded_hal::blocking::delay::DelayUs;
 
@@ -22,13 +22,13 @@ use embedded_graphics::{
     geometry::{Point, Size},
     pixelcolor::{raw::RawU16, Rgb565},
     prelude::*,
-    primitives::rectangle::*,
+    primitives::{rectangle::*, Circle, Line},
     style::{PrimitiveStyle, Styled},
     DrawTarget,
 };
 
 /// Screen rotation, CCW
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Rotation {
     R0,
     R90,
@@ -50,6 +50,37 @@ impl TryFrom<u32> for Rotation {
     }
 }
 
+impl Rotation {
+    /// Screen point (in this rotation) in the LCD native (R0) coordinates
+    pub fn native_point(self, p: Point) -> Point {
+        let w = TFT_WIDTH as i32 - 1;
+        let h = TFT_HEIGHT as i32 - 1;
+        match self {
+            Rotation::R0 => p,
+            Rotation::R90 => Point::new(w - p.y, p.x),
+            Rotation::R180 => Point::new(w - p.x, h - p.y),
+            Rotation::R270 => Point::new(p.y, h - p.x),
+        }
+    }
+
+    /// LCD native (R0) point in this rotation's screen coordinates
+    pub fn screen_point(self, p: Point) -> Point {
+        let w = TFT_WIDTH as i32 - 1;
+        let h = TFT_HEIGHT as i32 - 1;
+        match self {
+            Rotation::R0 => p,
+            Rotation::R90 => Point::new(p.y, w - p.x),
+            Rotation::R180 => Point::new(w - p.x, h - p.y),
+            Rotation::R270 => Point::new(h - p.y, p.x),
+        }
+    }
+
+    /// Native display size, as seen without rotation
+    pub fn native_size() -> Size {
+        TFT_NATIVE_SIZE
+    }
+}
+
 /// ILI9328
 /// https://cdn-shop.adafruit.com/datasheets/ILI9328.pdf
 pub struct Lcd<D> {
@@ -85,10 +116,7 @@ where
 
     fn draw_pixel(&mut self, pixel: Pixel<Rgb565>) -> Result<(), Self::Error> {
         let Pixel(p, color) = pixel;
-        let lcdp = self.lcd_point(p);
-
-        self.write_register(ILI932XRegister::GramHorAd as u16, lcdp.x as u16)?;
-        self.write_register(ILI932XRegister::GramVerAd as u16, lcdp.y as u16)?;
+        self.set_gram_address(p)?;
 
         self.transact(|slf| {
             slf.write_register_index(ILI932XRegister::RwGram as u16)?;
@@ -98,13 +126,162 @@ where
         })
     }
 
+    /// Pixels continuing a row to the right go out in the same GRAM burst,
+    /// EntryMod moves the address counter that way in every rotation.
+    /// The address is only sent when a run breaks, off screen pixels are dropped.
+    fn draw_iter<T>(&mut self, item: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = Pixel<Rgb565>>,
+    {
+        let Size { width, height } = self.size();
+
+        // where the address counter points while a burst is open, and what's on the bus
+        let mut next: Option<Point> = None;
+        let mut bus = Rgb565::BLACK;
+
+        for Pixel(p, color) in item {
+            if p.x < 0 || p.y < 0 || p.x >= width as i32 || p.y >= height as i32 {
+                continue;
+            }
+
+            if next != Some(p) {
+                if next.is_some() {
+                    self.end_transaction()?;
+                }
+                self.set_gram_address(p)?;
+                self.begin_gram_write()?;
+                self.write_data_word(RawU16::from(color).into_inner())?;
+            } else if color == bus {
+                self.strobe_write()?;
+            } else {
+                self.write_data_word(RawU16::from(color).into_inner())?;
+            }
+
+            bus = color;
+            next = Some(p + Point::new(1, 0));
+        }
+
+        if next.is_some() {
+            self.end_transaction()?;
+        }
+        Ok(())
+    }
+
+    fn draw_line(
+        &mut self,
+        item: &Styled<Line, PrimitiveStyle<Rgb565>>,
+    ) -> Result<(), Self::Error> {
+        match item.style.stroke_color {
+            Some(c) if item.style.stroke_width == 1 => {
+                self.line_spans(item.primitive.start, item.primitive.end, c)
+            }
+            // thick lines are left to embedded-graphics
+            _ => self.draw_iter(item),
+        }
+    }
+
     fn draw_rectangle(
         &mut self,
         item: &Styled<Rectangle, PrimitiveStyle<Rgb565>>,
     ) -> Result<(), Self::Error> {
-        for c in item.style.fill_color {
-            self.fill_rectangle(item.primitive, c)?;
+        let Rectangle {
+            top_left: tl,
+            bottom_right: br,
+        } = item.primitive;
+        let style = item.style;
+        let w = style.stroke_width as i32;
+
+        let stroke = match style.stroke_color {
+            Some(c) if w > 0 => c,
+            _ => {
+                if let Some(c) = style.fill_color {
+                    self.fill_rectangle(item.primitive, c)?;
+                }
+                return Ok(());
+            }
+        };
+
+        // stroke is inside of the rectangle, as in embedded-graphics
+        if let Some(c) = style.fill_color {
+            self.fill_rectangle(
+                Rectangle::new(tl + Point::new(w, w), br - Point::new(w, w)),
+                c,
+            )?;
+        }
+
+        let top = Rectangle::new(tl, Point::new(br.x, tl.y + w - 1));
+        let bottom = Rectangle::new(Point::new(tl.x, br.y - w + 1), br);
+        let left = Rectangle::new(
+            Point::new(tl.x, tl.y + w),
+            Point::new(tl.x + w - 1, br.y - w),
+        );
+        let right = Rectangle::new(
+            Point::new(br.x - w + 1, tl.y + w),
+            Point::new(br.x, br.y - w),
+        );
+        for r in [top, bottom, left, right].iter() {
+            self.fill_rectangle(*r, stroke)?;
         }
+
+        Ok(())
+    }
+
+    /// Row by row, each row is at most a fill span and two stroke spans
+    fn draw_circle(
+        &mut self,
+        item: &Styled<Circle, PrimitiveStyle<Rgb565>>,
+    ) -> Result<(), Self::Error> {
+        let Circle { center, radius } = item.primitive;
+        let style = item.style;
+        let r = radius as i32;
+
+        let stroke = style.stroke_color.filter(|_| style.stroke_width > 0);
+        let inner = match stroke {
+            Some(_) => r - style.stroke_width as i32,
+            None => r,
+        };
+
+        for dy in -r..=r {
+            let y = center.y + dy;
+            let outer_x = half_chord(r, dy);
+
+            if dy.abs() > inner {
+                // above or below the fill, stroke only
+                if let Some(c) = stroke {
+                    self.fill_span(
+                        Point::new(center.x - outer_x, y),
+                        Point::new(center.x + outer_x, y),
+                        c,
+                    )?;
+                }
+                continue;
+            }
+
+            let inner_x = match stroke {
+                Some(_) => half_chord(inner, dy).min(outer_x - 1),
+                None => outer_x,
+            };
+            if let Some(c) = style.fill_color {
+                self.fill_span(
+                    Point::new(center.x - inner_x, y),
+                    Point::new(center.x + inner_x, y),
+                    c,
+                )?;
+            }
+            if let Some(c) = stroke {
+                self.fill_span(
+                    Point::new(center.x - outer_x, y),
+                    Point::new(center.x - inner_x - 1, y),
+                    c,
+                )?;
+                self.fill_span(
+                    Point::new(center.x + inner_x + 1, y),
+                    Point::new(center.x + outer_x, y),
+                    c,
+                )?;
+            }
+        }
+
         Ok(())
     }
 
@@ -120,7 +297,6 @@ where
 
 const PUSH_PULL_1: u32 = 0b0011;
 const PUSH_PULL: u32 = PUSH_PULL_1
-    | PUSH_PULL_1
     | PUSH_PULL_1 << 4
     | PUSH_PULL_1 << 8
     | PUSH_PULL_1 << 12
@@ -131,7 +307,6 @@ const PUSH_PULL: u32 = PUSH_PULL_1
 
 const FLOATING_INPUT_1: u32 = 0b0100;
 const FLOATING_INPUT: u32 = FLOATING_INPUT_1
-    | FLOATING_INPUT_1
     | FLOATING_INPUT_1 << 4
     | FLOATING_INPUT_1 << 8
     | FLOATING_INPUT_1 << 12
@@ -208,6 +383,7 @@ impl<D> Lcd<D>
 where
     D: DelayMs<u16> + DelayUs<u16>,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         delay: D,
         port: GPIOE,
@@ -234,16 +410,24 @@ where
         })
     }
 
+    /// Backlight on or off, `init` turns it on
+    pub fn set_backlight(&mut self, on: bool) -> Result<(), LcdError> {
+        if on {
+            self.backlight.set_high()?;
+        } else {
+            self.backlight.set_low()?;
+        }
+        Ok(())
+    }
+
+    /// Also runs from the panic and HardFault handlers (`recover`), keep
+    /// semihosting and panics out of it: they'd fault again without a debugger
     pub fn init(&mut self) -> Result<(), LcdError> {
         self.backlight.set_high()?;
         self.output()?;
 
         self.delay.delay_ms(130);
 
-        let d1 = self.read_register(0)?;
-
-        hprintln!("ID?: {:X}", d1).unwrap();
-
         self.write_register(ILI932XRegister::StartOsc as u16, 0x0001)?;
 
         self.delay.delay_ms(50);
@@ -312,79 +496,152 @@ where
         Ok(())
     }
 
+    /// Takes the display over in whatever state it was left in (e.g. by a fault
+    /// handler): `init` when it's not on, otherwise back to R0, no window and no scroll
+    pub fn recover(&mut self) -> Result<(), LcdError> {
+        self.output()?;
+        self.csn.set_high()?;
+
+        // D1 D0, the display is on
+        let on = self.read_register(ILI932XRegister::DispCtrl1 as u16)? & 0x0003 == 0x0003;
+        if !on {
+            return self.init();
+        }
+
+        self.backlight.set_high()?;
+        self.set_rotation(Rotation::R0)?;
+        self.reset_window()?;
+        self.set_scroll(0)
+    }
+
     pub fn set_rotation(&mut self, rotation: Rotation) -> Result<(), LcdError> {
         self.rotation = rotation;
+        self.set_entry_mode(false)
+    }
 
-        self.write_register(
-            ILI932XRegister::EntryMod as u16,
-            (match self.rotation {
-                Rotation::R0 => EM_ID0 | EM_ID1,
-                Rotation::R90 => EM_AM | EM_ID1,
-                Rotation::R180 => 0,
-                Rotation::R270 => EM_AM | EM_ID0,
-            }) | EM_BGR,
-        )
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
     }
 
-    fn set_window(&mut self, window: Rectangle) -> Result<(), LcdError> {
-        let Rectangle {
-            top_left,
-            bottom_right,
-        } = window;
-        if top_left.x <= bottom_right.x && top_left.y <= bottom_right.y {
-            Ok(())
-        } else {
-            Err(LcdError::InvalidWindow)
-        }?;
+    /// GRAM address counter goes left to right in rotated coordinates,
+    /// lines go top to bottom or, flipped, bottom to top.
+    fn set_entry_mode(&mut self, bottom_up: bool) -> Result<(), LcdError> {
+        // (increment mode, bit that controls line direction)
+        let (mode, line_dir) = match self.rotation {
+            Rotation::R0 => (EM_ID0 | EM_ID1, EM_ID1),
+            Rotation::R90 => (EM_AM | EM_ID1, EM_ID0),
+            Rotation::R180 => (0, EM_ID1),
+            Rotation::R270 => (EM_AM | EM_ID0, EM_ID0),
+        };
+
+        let mode = if bottom_up { mode ^ line_dir } else { mode };
+        self.write_register(ILI932XRegister::EntryMod as u16, mode | EM_BGR)
+    }
+
+    /// Sets GRAM window to an area in rotated coordinates and moves
+    /// GRAM address to its top left (or bottom left) corner.
+    fn set_area(&mut self, top_left: Point, size: Size, bottom_up: bool) -> Result<(), LcdError> {
+        let Size { width, height } = self.size();
+        if size.width == 0
+            || size.height == 0
+            || top_left.x < 0
+            || top_left.y < 0
+            || top_left.x as u32 + size.width > width
+            || top_left.y as u32 + size.height > height
+        {
+            return Err(LcdError::InvalidWindow);
+        }
 
         let tl = self.lcd_point(top_left);
-        let br = self.lcd_point(bottom_right - Point::new(1, 1));
+        let br =
+            self.lcd_point(top_left + Point::new(size.width as i32 - 1, size.height as i32 - 1));
 
         let minx = tl.x.min(br.x) as u16;
         let miny = tl.y.min(br.y) as u16;
         let maxx = tl.x.max(br.x) as u16;
         let maxy = tl.y.max(br.y) as u16;
 
-        hprintln!(
-            "win: minx: {} miny: {} / maxx: {} maxy: {}",
-            minx,
-            miny,
-            maxx,
-            maxy
-        )
-        .unwrap();
-
         self.write_register(ILI932XRegister::HorStartAd as u16, minx)?;
         self.write_register(ILI932XRegister::HorEndAd as u16, maxx)?;
 
         self.write_register(ILI932XRegister::VerStartAd as u16, miny)?;
         self.write_register(ILI932XRegister::VerEndAd as u16, maxy)?;
 
-        self.write_register(ILI932XRegister::GramHorAd as u16, minx)?;
-        self.write_register(ILI932XRegister::GramVerAd as u16, miny)?;
+        // GRAM address counter moves according to EntryMod,
+        // rotated top left (bottom left) corner is where it starts
+        let start = if bottom_up {
+            self.lcd_point(top_left + Point::new(0, size.height as i32 - 1))
+        } else {
+            tl
+        };
+        self.write_register(ILI932XRegister::GramHorAd as u16, start.x as u16)?;
+        self.write_register(ILI932XRegister::GramVerAd as u16, start.y as u16)?;
 
         Ok(())
     }
 
+    /// Moves GRAM address to a point in rotated coordinates
+    fn set_gram_address(&mut self, p: Point) -> Result<(), LcdError> {
+        let lcdp = self.lcd_point(p);
+        self.write_register(ILI932XRegister::GramHorAd as u16, lcdp.x as u16)?;
+        self.write_register(ILI932XRegister::GramVerAd as u16, lcdp.y as u16)
+    }
+
     fn reset_window(&mut self) -> Result<(), LcdError> {
         self.write_register(ILI932XRegister::HorStartAd as u16, 0)?;
-        self.write_register(ILI932XRegister::HorEndAd as u16, TFT_WIDTH - 1 as u16)?;
+        self.write_register(ILI932XRegister::HorEndAd as u16, TFT_WIDTH - 1)?;
 
         self.write_register(ILI932XRegister::VerStartAd as u16, 0)?;
-        self.write_register(ILI932XRegister::VerEndAd as u16, TFT_HEIGHT - 1 as u16)?;
+        self.write_register(ILI932XRegister::VerEndAd as u16, TFT_HEIGHT - 1)?;
 
         Ok(())
     }
 
     /// Fills a rectangle with a solid color.
-    /// Top left / bottom right points included.
+    /// Top left / bottom right points included, clipped to the screen.
     fn fill_rectangle(&mut self, rectangle: Rectangle, color: Rgb565) -> Result<(), LcdError> {
-        self.set_window(rectangle)?; // validates input
+        let Size { width, height } = self.size();
+        let Rectangle {
+            top_left,
+            bottom_right,
+        } = rectangle;
+
+        let x0 = top_left.x.max(0);
+        let y0 = top_left.y.max(0);
+        let x1 = bottom_right.x.min(width as i32 - 1);
+        let y1 = bottom_right.y.min(height as i32 - 1);
+        if x0 > x1 || y0 > y1 {
+            // nothing on screen
+            return Ok(());
+        }
 
-        let Size { width, height } = rectangle.size();
-        let mut n = width * height;
+        self.fill_area(
+            Point::new(x0, y0),
+            Size::new((x1 - x0 + 1) as u32, (y1 - y0 + 1) as u32),
+            color,
+        )
+    }
 
-        hprintln!("fill: w: {} h: {} n: {}", width, height, n).unwrap();
+    /// Horizontal or vertical span between two points, both included.
+    /// Goes out as a one pixel high (wide) window fill.
+    fn fill_span(&mut self, a: Point, b: Point, color: Rgb565) -> Result<(), LcdError> {
+        self.fill_rectangle(
+            Rectangle::new(
+                Point::new(a.x.min(b.x), a.y.min(b.y)),
+                Point::new(a.x.max(b.x), a.y.max(b.y)),
+            ),
+            color,
+        )
+    }
+
+    fn fill_area(&mut self, top_left: Point, size: Size, color: Rgb565) -> Result<(), LcdError> {
+        self.set_area(top_left, size, false)?; // validates input
+        self.fill_window(size.width * size.height, color)
+    }
+
+    /// Writes `n` pixels of one color into the current window
+    fn fill_window(&mut self, n: u32, color: Rgb565) -> Result<(), LcdError> {
+        let mut n = n;
 
         let mut leftover = 0;
         self.transact(|slf| {
@@ -400,9 +657,7 @@ where
 
             while n > 0 {
                 slf.delay.delay_us(1);
-                slf.wrn.set_low()?;
-                slf.delay.delay_us(1);
-                slf.wrn.set_high()?;
+                slf.strobe_write()?;
 
                 n -= 1;
             }
@@ -415,6 +670,188 @@ where
         Ok(())
     }
 
+    /// One pixel wide line, Bresenham steps along the major axis
+    /// are collected into spans.
+    fn line_spans(&mut self, start: Point, end: Point, color: Rgb565) -> Result<(), LcdError> {
+        let dx = (end.x - start.x).abs();
+        let dy = -(end.y - start.y).abs();
+        let sx = if start.x < end.x { 1 } else { -1 };
+        let sy = if start.y < end.y { 1 } else { -1 };
+        let steep = -dy > dx;
+
+        let mut err = dx + dy;
+        let mut p = start;
+        let mut span_start = start;
+
+        while p != end {
+            let mut next = p;
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                next.x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                next.y += sy;
+            }
+
+            // a step across the major axis ends the span
+            if (steep && next.x != p.x) || (!steep && next.y != p.y) {
+                self.fill_span(span_start, p, color)?;
+                span_start = next;
+            }
+            p = next;
+        }
+
+        self.fill_span(span_start, p, color)
+    }
+
+    /// Streams pixels into a window in a single GRAM transaction.
+    /// Window is given in rotated coordinates and has to fit on the screen,
+    /// pixels go left to right, top to bottom.
+    pub fn stream<F, E>(&mut self, top_left: Point, size: Size, f: F) -> Result<(), E>
+    where
+        F: FnOnce(&mut Gram<D>) -> Result<(), E>,
+        E: From<LcdError>,
+    {
+        self.stream_window(top_left, size, false, f)
+    }
+
+    /// Same as `stream` but lines go bottom to top, e.g. for bottom-up BMP files.
+    pub fn stream_bottom_up<F, E>(&mut self, top_left: Point, size: Size, f: F) -> Result<(), E>
+    where
+        F: FnOnce(&mut Gram<D>) -> Result<(), E>,
+        E: From<LcdError>,
+    {
+        self.stream_window(top_left, size, true, f)
+    }
+
+    fn stream_window<F, E>(
+        &mut self,
+        top_left: Point,
+        size: Size,
+        bottom_up: bool,
+        f: F,
+    ) -> Result<(), E>
+    where
+        F: FnOnce(&mut Gram<D>) -> Result<(), E>,
+        E: From<LcdError>,
+    {
+        self.set_area(top_left, size, bottom_up)?;
+        if bottom_up {
+            self.set_entry_mode(true)?;
+        }
+        self.begin_gram_write()?;
+
+        let res = f(&mut Gram {
+            remaining: size.width * size.height,
+            lcd: self,
+        });
+
+        self.end_transaction()?;
+        if bottom_up {
+            self.set_entry_mode(false)?;
+        }
+        self.reset_window()?;
+
+        res
+    }
+
+    /// Reads a window of GRAM back into `buf`, left to right, top to bottom
+    /// in rotated coordinates. Window has to fit on the screen.
+    pub fn read_pixels(
+        &mut self,
+        top_left: Point,
+        size: Size,
+        buf: &mut [Rgb565],
+    ) -> Result<(), LcdError> {
+        self.set_area(top_left, size, false)?;
+        let n = buf.len().min((size.width * size.height) as usize);
+
+        let res = self.transact(|slf| {
+            slf.write_register_index(ILI932XRegister::RwGram as u16)?;
+            slf.delay.delay_us(1);
+            slf.input()?;
+
+            // first read after selecting GRAM returns garbage
+            slf.read_port_word()?;
+            for c in buf[..n].iter_mut() {
+                *c = gram_color(slf.read_port_word()?);
+            }
+
+            slf.output()
+        });
+
+        self.reset_window()?;
+        res
+    }
+
+    /// Writes pixels into a window, see `stream`.
+    pub fn write_pixels<I>(
+        &mut self,
+        top_left: Point,
+        size: Size,
+        colors: I,
+    ) -> Result<(), LcdError>
+    where
+        I: IntoIterator<Item = Rgb565>,
+    {
+        self.stream(top_left, size, |gram| {
+            for c in colors {
+                gram.push(c)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Writes (color, count) runs into a window, see `stream`.
+    pub fn write_runs<I>(&mut self, top_left: Point, size: Size, runs: I) -> Result<(), LcdError>
+    where
+        I: IntoIterator<Item = (Rgb565, u32)>,
+    {
+        self.stream(top_left, size, |gram| {
+            for (c, n) in runs {
+                gram.push_run(c, n)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// GRAM lines along the native long side, what `set_scroll` wraps around
+    pub fn gram_lines(&self) -> u16 {
+        TFT_HEIGHT
+    }
+
+    /// Hardware scroll: the panel shows GRAM starting `lines` further along
+    /// the native long side, wrapping around. Independent of the rotation,
+    /// drawing still goes to unscrolled coordinates.
+    pub fn set_scroll(&mut self, lines: u16) -> Result<(), LcdError> {
+        self.write_register(ILI932XRegister::GateScanCtrl3 as u16, lines % TFT_HEIGHT)
+    }
+
+    /// Fills whole GRAM lines in native coordinates, e.g. ones scrolled out of view
+    pub fn fill_gram_lines(
+        &mut self,
+        first: u16,
+        count: u16,
+        color: Rgb565,
+    ) -> Result<(), LcdError> {
+        if count == 0 || first as u32 + count as u32 > TFT_HEIGHT as u32 {
+            return Err(LcdError::InvalidWindow);
+        }
+
+        self.write_register(ILI932XRegister::HorStartAd as u16, 0)?;
+        self.write_register(ILI932XRegister::HorEndAd as u16, TFT_WIDTH - 1)?;
+        self.write_register(ILI932XRegister::VerStartAd as u16, first)?;
+        self.write_register(ILI932XRegister::VerEndAd as u16, first + count - 1)?;
+
+        // one color, the address counter wraps inside the window whichever way it goes
+        self.write_register(ILI932XRegister::GramHorAd as u16, 0)?;
+        self.write_register(ILI932XRegister::GramVerAd as u16, first)?;
+
+        self.fill_window(TFT_WIDTH as u32 * count as u32, color)
+    }
+
     pub fn max_btm_right(&self) -> Point {
         let w = TFT_WIDTH as i32 - 1;
         let h = TFT_HEIGHT as i32 - 1;
@@ -466,6 +903,26 @@ where
         res
     }
 
+    /// Opens a transaction and selects GRAM for writing, stays open until `end_transaction`
+    fn begin_gram_write(&mut self) -> Result<(), LcdError> {
+        self.rs.set_high()?;
+        self.rdn.set_high()?;
+        self.wrn.set_high()?;
+
+        self.csn.set_low()?;
+        self.delay.delay_us(1);
+
+        self.write_register_index(ILI932XRegister::RwGram as u16)?;
+        self.delay.delay_us(1);
+        Ok(())
+    }
+
+    fn end_transaction(&mut self) -> Result<(), LcdError> {
+        self.delay.delay_us(1);
+        self.csn.set_high()?;
+        Ok(())
+    }
+
     fn write_register_index(&mut self, register: u16) -> Result<(), LcdError> {
         self.rs.set_low()?;
         self.write_port_bits(register)?;
@@ -480,7 +937,11 @@ where
 
     fn write_data_word(&mut self, data: u16) -> Result<(), LcdError> {
         self.write_port_bits(data)?;
+        self.strobe_write()
+    }
 
+    /// Latches whatever is on the port, again
+    fn strobe_write(&mut self) -> Result<(), LcdError> {
         self.wrn.set_low()?;
         self.delay.delay_us(1);
         self.wrn.set_high()?;
@@ -489,14 +950,21 @@ where
 
     fn read_data_word(&mut self) -> Result<u16, LcdError> {
         self.input()?;
+        let res = self.read_port_word()?;
+        self.output()?;
+
+        Ok(res)
+    }
 
+    /// Port has to be switched to input already
+    fn read_port_word(&mut self) -> Result<u16, LcdError> {
         self.rdn.set_low()?;
         self.delay.delay_us(1);
 
         let res = self.port.idr.read().bits();
 
         self.rdn.set_high()?;
-        self.output()?;
+        self.delay.delay_us(1);
 
         Ok(res as u16)
     }
@@ -538,3 +1006,54 @@ where
         }
     }
 }
+
+/// Half width of row `dy` of a circle, rounded the way the radius looks best
+fn half_chord(radius: i32, dy: i32) -> i32 {
+    isqrt((radius * radius + radius - dy * dy).max(0) as u64) as i32
+}
+
+/// GRAM keeps colors as BGR (EM_BGR), reads come back with red and blue swapped
+fn gram_color(bgr: u16) -> Rgb565 {
+    Rgb565::from(RawU16::new(
+        (bgr & 0x001f) << 11 | (bgr & 0x07e0) | (bgr & 0xf800) >> 11,
+    ))
+}
+
+/// Sequential writer into the current GRAM window, see `Lcd::stream`.
+/// Pixels past the end of the window are dropped.
+pub struct Gram<'a, D> {
+    lcd: &'a mut Lcd<D>,
+    remaining: u32,
+}
+
+impl<'a, D> Gram<'a, D>
+where
+    D: DelayMs<u16> + DelayUs<u16>,
+{
+    /// Writes the next pixel
+    pub fn push(&mut self, color: Rgb565) -> Result<(), LcdError> {
+        self.push_run(color, 1)
+    }
+
+    /// Writes `count` pixels of the same color,
+    /// color is put on the bus once and then latched `count` times.
+    pub fn push_run(&mut self, color: Rgb565, count: u32) -> Result<(), LcdError> {
+        let count = count.min(self.remaining);
+        if count == 0 {
+            return Ok(());
+        }
+
+        self.lcd.write_data_word(RawU16::from(color).into_inner())?;
+        for _ in 1..count {
+            self.lcd.strobe_write()?;
+        }
+
+        self.remaining -= count;
+        Ok(())
+    }
+
+    /// Pixels left until the window is full
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+}
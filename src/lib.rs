@@ -1,8 +1,26 @@
 //#![deny(unsafe_code)]
 //#![deny(warnings)]
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+pub mod beeper;
+pub mod blend;
+pub mod board;
+pub mod boot;
+pub mod clocks;
 pub mod consts;
 pub mod delay;
+pub mod dither;
+pub mod fault;
+pub mod font;
+pub mod image;
+pub mod indexed;
 pub mod lcd;
+mod math;
+pub mod melody;
+pub mod settings;
+pub mod sprite;
+pub mod terminal;
+pub mod touch;
 pub mod types;
+pub mod ui;
+pub mod watchdog;
@@ -0,0 +1,24 @@
+//
+// Integer helpers shared by the drawing code
+//
+
+/// Integer square root, rounded down
+pub(crate) fn isqrt(n: u64) -> u64 {
+    let mut x = 0u64;
+    let mut bit = 1u64 << 62;
+    let mut n = n;
+
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if n >= x + bit {
+            n -= x + bit;
+            x = (x >> 1) + bit;
+        } else {
+            x >>= 1;
+        }
+        bit >>= 2;
+    }
+    x
+}
@@ -0,0 +1,580 @@
+//
+// Melodies for the beeper: RTTTL (Nokia ring tone) strings or const note arrays,
+// played by priority so an alarm cuts a key click (or anything less urgent) short.
+//
+// RTTTL: "name:d=4,o=5,b=120:8c6,8e6,4g6.,p,16a#5"
+//   defaults: d duration (1, 2, 4, 8, 16, 32 - a fraction of a whole note),
+//             o octave, b beats (quarter notes) per minute
+//   notes: [duration] letter (a-g, h = b, p = pause) [#] [.] [octave] [.]
+//
+// A `Player` feeds the `Beeper` queue a few notes at a time. Every melody started
+// gets a new generation, the RTIC task playing it carries the generation it was
+// spawned for and stale ones stop, so the new melody starts right away instead of
+// after the note that was sounding. `start` spawns that task itself and leaves
+// the old melody alone when it can't (the task queue is full):
+//
+//   let spawn = cx.spawn;
+//   player.start(beeper, sounds::ERROR, Priority::Alert, |g| spawn.beep(g));
+//
+//   #[task(capacity = 2, resources = [beeper, player], schedule = [beep])]
+//   fn beep(cx: beep::Context, generation: u32) {
+//       if let Some(ms) = cx.resources.player.next(cx.resources.beeper, generation) {
+//           cx.schedule.beep(cx.scheduled + ..ms.., generation).ok();
+//       }
+//   }
+//
+use embedded_hal::Pwm;
+use stm32f1xx_hal::time::Hertz;
+
+use crate::beeper::{Beeper, Note, QUEUE_LEN};
+
+/// Octave 4, C4 to B4 in Hz
+const OCTAVE4: [u16; 12] = [262, 277, 294, 311, 330, 349, 370, 392, 415, 440, 466, 494];
+
+/// Notes handed to the beeper ahead of time
+const LOOKAHEAD: usize = 4;
+
+/// Longest note played, RTIC schedules at most ~29s ahead at 72MHz
+pub const MAX_NOTE_MS: u32 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pitch {
+    C,
+    Cs,
+    D,
+    Ds,
+    E,
+    F,
+    Fs,
+    G,
+    Gs,
+    A,
+    As,
+    B,
+}
+
+/// Frequency of a note, octaves 1 to 9, for const note arrays:
+/// `Note::new(pitch(Pitch::E, 6), 80)`
+pub const fn pitch(pitch: Pitch, octave: u8) -> u16 {
+    frequency(pitch as usize, octave)
+}
+
+/// `semitone` 0 is C, 12 the next octave's C
+const fn frequency(semitone: usize, octave: u8) -> u16 {
+    let (semitone, octave) = if semitone >= 12 {
+        (semitone - 12, octave + 1)
+    } else {
+        (semitone, octave)
+    };
+    let f = OCTAVE4[semitone];
+    if octave >= 4 {
+        f << (octave - 4)
+    } else {
+        f >> (4 - octave)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MelodyError {
+    /// Bad header or note, byte offset in the string
+    Syntax(usize),
+}
+
+/// Plays through RTTTL notes
+#[derive(Debug, Clone)]
+pub struct Rtttl<'a> {
+    text: &'a [u8],
+    pos: usize,
+    duration: u16,
+    octave: u8,
+    bpm: u16,
+}
+
+impl<'a> Rtttl<'a> {
+    /// Checks the header, the notes are parsed as they're played
+    pub fn new(text: &'a str) -> Result<Self, MelodyError> {
+        let text = text.as_bytes();
+        let name_end = find(text, 0, b':').ok_or(MelodyError::Syntax(0))?;
+        let defaults_end = find(text, name_end + 1, b':').ok_or(MelodyError::Syntax(name_end))?;
+
+        let mut rtttl = Rtttl {
+            text,
+            pos: defaults_end + 1,
+            duration: 4,
+            octave: 6,
+            bpm: 63,
+        };
+
+        let mut pos = name_end + 1;
+        while pos < defaults_end {
+            let end = find(&text[..defaults_end], pos, b',').unwrap_or(defaults_end);
+            let field = trim(&text[pos..end]);
+            if !field.is_empty() {
+                let syntax = MelodyError::Syntax(pos);
+                if field.len() < 3 || field[1] != b'=' {
+                    return Err(syntax);
+                }
+                let value = number(&field[2..]).ok_or(syntax)?;
+                match field[0].to_ascii_lowercase() {
+                    b'd' if is_duration(value) => rtttl.duration = value as u16,
+                    b'o' if is_octave(value) => rtttl.octave = value as u8,
+                    b'b' if value > 0 && value <= 900 => rtttl.bpm = value as u16,
+                    _ => return Err(syntax),
+                }
+            }
+            pos = end + 1;
+        }
+
+        Ok(rtttl)
+    }
+
+    /// Whole note in ms
+    fn whole(&self) -> u32 {
+        4 * 60_000 / self.bpm as u32
+    }
+
+    fn note(&self, field: &[u8], at: usize) -> Result<Note, MelodyError> {
+        let syntax = MelodyError::Syntax(at);
+        let digits = field.iter().take_while(|c| c.is_ascii_digit()).count();
+        let duration = if digits > 0 {
+            number(&field[..digits])
+                .filter(|d| is_duration(*d))
+                .ok_or(syntax)?
+        } else {
+            self.duration as u32
+        };
+
+        let mut rest = &field[digits..];
+        let letter = *rest.first().ok_or(syntax)?;
+        rest = &rest[1..];
+        let mut semitone = match letter.to_ascii_lowercase() {
+            b'c' => Some(0),
+            b'd' => Some(2),
+            b'e' => Some(4),
+            b'f' => Some(5),
+            b'g' => Some(7),
+            b'a' => Some(9),
+            b'b' | b'h' => Some(11),
+            b'p' => None,
+            _ => return Err(syntax),
+        };
+        if rest.first() == Some(&b'#') {
+            semitone = semitone.map(|s| s + 1);
+            rest = &rest[1..];
+        }
+
+        // the dot goes before or after the octave
+        let mut dotted = false;
+        if rest.first() == Some(&b'.') {
+            dotted = true;
+            rest = &rest[1..];
+        }
+        let mut octave = self.octave;
+        if let Some(c) = rest.first().filter(|c| c.is_ascii_digit()) {
+            let o = (c - b'0') as u32;
+            if !is_octave(o) {
+                return Err(syntax);
+            }
+            octave = o as u8;
+            rest = &rest[1..];
+        }
+        if rest.first() == Some(&b'.') {
+            dotted = true;
+            rest = &rest[1..];
+        }
+        if !rest.is_empty() {
+            return Err(syntax);
+        }
+
+        let mut ms = self.whole() / duration;
+        if dotted {
+            ms += ms / 2;
+        }
+        let freq = semitone.map_or(0, |s| frequency(s, octave));
+        // a whole note at b=1 is 4 minutes
+        Ok(Note::new(freq, ms.min(MAX_NOTE_MS) as u16))
+    }
+}
+
+impl<'a> Iterator for Rtttl<'a> {
+    type Item = Result<Note, MelodyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pos < self.text.len() {
+            let start = self.pos;
+            let end = find(self.text, start, b',').unwrap_or(self.text.len());
+            self.pos = end + 1;
+
+            let field = trim(&self.text[start..end]);
+            if !field.is_empty() {
+                return Some(self.note(field, start));
+            }
+        }
+        None
+    }
+}
+
+/// Something to play
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Melody {
+    /// One note made up at run time, e.g. a configured key click
+    Tone(Note),
+    Notes(&'static [Note]),
+    Rtttl(&'static str),
+}
+
+/// Who gets the beeper, higher ones cut lower ones short
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Click,
+    Notify,
+    Alert,
+    Alarm,
+}
+
+enum Source {
+    Tone(Option<Note>),
+    Notes(&'static [Note]),
+    Rtttl(Rtttl<'static>),
+}
+
+impl Source {
+    /// Bad RTTTL ends the melody there
+    fn next(&mut self) -> Option<Note> {
+        match self {
+            Source::Tone(note) => note.take(),
+            Source::Notes(notes) => {
+                let all: &'static [Note] = notes;
+                let (first, rest) = all.split_first()?;
+                *notes = rest;
+                Some(*first)
+            }
+            Source::Rtttl(rtttl) => rtttl.next()?.ok(),
+        }
+    }
+}
+
+pub struct Player {
+    source: Option<Source>,
+    priority: Priority,
+    generation: u32,
+}
+
+impl Player {
+    pub fn new() -> Self {
+        Player {
+            source: None,
+            priority: Priority::Click,
+            generation: 0,
+        }
+    }
+
+    /// Stops whatever plays unless it's more important, false then.
+    /// Bad RTTTL headers are turned down too, and so is `melody` when `spawn`
+    /// fails to start the task playing its generation (e.g. `cx.spawn.beep`).
+    pub fn start<P, F>(
+        &mut self,
+        beeper: &mut Beeper<P>,
+        melody: Melody,
+        priority: Priority,
+        spawn: F,
+    ) -> bool
+    where
+        P: Pwm<Time = Hertz, Duty = u16>,
+        P::Channel: Copy,
+        F: FnOnce(u32) -> Result<(), u32>,
+    {
+        if self.is_busy(beeper) && priority < self.priority {
+            return false;
+        }
+        let source = match melody {
+            Melody::Tone(note) => Source::Tone(Some(note)),
+            Melody::Notes(notes) => Source::Notes(notes),
+            Melody::Rtttl(text) => match Rtttl::new(text) {
+                Ok(rtttl) => Source::Rtttl(rtttl),
+                Err(_) => return false,
+            },
+        };
+
+        // the task can't run before this returns, it needs the player
+        let generation = self.generation.wrapping_add(1);
+        if spawn(generation).is_err() {
+            return false;
+        }
+
+        beeper.stop();
+        self.source = Some(source);
+        self.priority = priority;
+        self.generation = generation;
+        true
+    }
+
+    /// Current melody, the task playing an older one has to stop
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Melody running, or notes queued on the beeper directly
+    pub fn is_busy<P>(&self, beeper: &Beeper<P>) -> bool
+    where
+        P: Pwm<Time = Hertz, Duty = u16>,
+        P::Channel: Copy,
+    {
+        self.source.is_some() || beeper.is_playing()
+    }
+
+    /// Priority of the melody playing, if any
+    pub fn priority(&self) -> Option<Priority> {
+        self.source.as_ref().map(|_| self.priority)
+    }
+
+    /// Plays the next note of melody `generation`, returns how many ms it lasts
+    /// (at most `MAX_NOTE_MS`, longer ones are cut), None once done or when
+    /// another melody took over
+    pub fn next<P>(&mut self, beeper: &mut Beeper<P>, generation: u32) -> Option<u32>
+    where
+        P: Pwm<Time = Hertz, Duty = u16>,
+        P::Channel: Copy,
+    {
+        if generation != self.generation {
+            return None;
+        }
+
+        if let Some(source) = self.source.as_mut() {
+            while beeper.queued() < LOOKAHEAD.min(QUEUE_LEN) {
+                match source.next() {
+                    Some(note) => {
+                        if beeper.play(note).is_err() {
+                            break;
+                        }
+                    }
+                    None => {
+                        self.source = None;
+                        break;
+                    }
+                }
+            }
+        }
+        beeper.next_note().map(|ms| ms.min(MAX_NOTE_MS))
+    }
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Player::new()
+    }
+}
+
+/// Stock sounds
+pub mod sounds {
+    use super::{pitch, Melody, Pitch::*};
+    use crate::beeper::Note;
+
+    pub const CLICK: Melody = Melody::Notes(&[Note::new(pitch(C, 7), 15)]);
+
+    pub const PRINT_COMPLETE: Melody = Melody::Rtttl("done:d=8,o=6,b=160:c,e,g,4c7,p,g,2c7");
+
+    pub const ERROR: Melody = Melody::Notes(&[
+        Note::new(pitch(A, 5), 150),
+        Note::rest(50),
+        Note::new(pitch(E, 5), 300),
+    ]);
+
+    pub const ALARM: Melody = Melody::Notes(&[
+        Note::new(pitch(A, 6), 200),
+        Note::new(pitch(E, 6), 200),
+        Note::new(pitch(A, 6), 200),
+        Note::new(pitch(E, 6), 200),
+        Note::rest(400),
+    ]);
+}
+
+fn find(text: &[u8], from: usize, c: u8) -> Option<usize> {
+    text.get(from..)?
+        .iter()
+        .position(|b| *b == c)
+        .map(|i| from + i)
+}
+
+fn trim(mut s: &[u8]) -> &[u8] {
+    while let Some((first, rest)) = s.split_first() {
+        if !first.is_ascii_whitespace() {
+            break;
+        }
+        s = rest;
+    }
+    while let Some((last, rest)) = s.split_last() {
+        if !last.is_ascii_whitespace() {
+            break;
+        }
+        s = rest;
+    }
+    s
+}
+
+fn number(s: &[u8]) -> Option<u32> {
+    if s.is_empty() || s.len() > 5 {
+        return None;
+    }
+    s.iter().try_fold(0u32, |n, c| {
+        if c.is_ascii_digit() {
+            Some(n * 10 + (c - b'0') as u32)
+        } else {
+            None
+        }
+    })
+}
+
+fn is_duration(d: u32) -> bool {
+    matches!(d, 1 | 2 | 4 | 8 | 16 | 32)
+}
+
+fn is_octave(o: u32) -> bool {
+    (1..=8).contains(&o)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the period the beeper sets
+    struct Pwm {
+        period: u32,
+        enabled: bool,
+    }
+
+    impl embedded_hal::Pwm for Pwm {
+        type Channel = ();
+        type Time = Hertz;
+        type Duty = u16;
+
+        fn disable(&mut self, _: ()) {
+            self.enabled = false;
+        }
+
+        fn enable(&mut self, _: ()) {
+            self.enabled = true;
+        }
+
+        fn get_period(&self) -> Hertz {
+            Hertz(self.period)
+        }
+
+        fn get_duty(&self, _: ()) -> u16 {
+            0
+        }
+
+        fn get_max_duty(&self) -> u16 {
+            1000
+        }
+
+        fn set_duty(&mut self, _: (), _: u16) {}
+
+        fn set_period<T: Into<Hertz>>(&mut self, period: T) {
+            self.period = period.into().0;
+        }
+    }
+
+    fn beeper() -> Beeper<Pwm> {
+        Beeper::new(
+            Pwm {
+                period: 0,
+                enabled: false,
+            },
+            (),
+        )
+    }
+
+    fn notes(text: &'static str) -> impl Iterator<Item = Note> {
+        Rtttl::new(text).unwrap().map(Result::unwrap)
+    }
+
+    #[test]
+    fn parses_rtttl() {
+        // b=120: a whole note is 2s
+        let mut n = notes("test:d=4,o=5,b=120:8c6,e,4g.,p,16a#5,2b.4");
+        assert_eq!(n.next(), Some(Note::new(1048, 250)));
+        assert_eq!(n.next(), Some(Note::new(660, 500)));
+        assert_eq!(n.next(), Some(Note::new(784, 750)));
+        assert_eq!(n.next(), Some(Note::rest(500)));
+        assert_eq!(n.next(), Some(Note::new(932, 125)));
+        assert_eq!(n.next(), Some(Note::new(494, 1500)));
+        assert_eq!(n.next(), None);
+    }
+
+    #[test]
+    fn rtttl_defaults() {
+        // d=4, o=6, b=63: a quarter note is 240000 / 63 / 4 ms
+        let mut n = notes("defaults::c, h5");
+        assert_eq!(n.next(), Some(Note::new(1048, 952)));
+        assert_eq!(n.next(), Some(Note::new(988, 952)));
+        assert_eq!(n.next(), None);
+    }
+
+    #[test]
+    fn caps_long_notes() {
+        // a whole note at b=1 is 4 minutes
+        let mut n = notes("slow:d=1,o=5,b=1:c,32p");
+        assert_eq!(n.next(), Some(Note::new(524, MAX_NOTE_MS as u16)));
+        assert_eq!(n.next(), Some(Note::rest(7_500)));
+    }
+
+    #[test]
+    fn rtttl_errors() {
+        assert_eq!(Rtttl::new("no header").err(), Some(MelodyError::Syntax(0)));
+        assert_eq!(Rtttl::new("x:d=3:c").err(), Some(MelodyError::Syntax(2)));
+        assert_eq!(Rtttl::new("x:b=0:c").err(), Some(MelodyError::Syntax(2)));
+
+        let mut rtttl = Rtttl::new("x::c,9c,q").unwrap();
+        assert!(rtttl.next().unwrap().is_ok());
+        assert_eq!(rtttl.next(), Some(Err(MelodyError::Syntax(5))));
+        assert_eq!(rtttl.next(), Some(Err(MelodyError::Syntax(8))));
+    }
+
+    #[test]
+    fn priorities() {
+        let mut beeper = beeper();
+        let mut player = Player::new();
+        let mut spawned = None;
+
+        assert!(
+            player.start(&mut beeper, sounds::ALARM, Priority::Alarm, |g| {
+                spawned = Some(g);
+                Ok(())
+            })
+        );
+        let generation = player.generation();
+        assert_eq!(spawned, Some(generation));
+        assert_eq!(player.next(&mut beeper, generation), Some(200));
+        assert_eq!(player.priority(), Some(Priority::Alarm));
+
+        // less important, turned down without a task
+        let click = Melody::Tone(Note::new(4_000, 8));
+        assert!(!player.start(&mut beeper, click, Priority::Click, |_| panic!()));
+        assert_eq!(player.generation(), generation);
+        assert_eq!(player.next(&mut beeper, generation), Some(200));
+
+        // as important cuts in, the old task stops
+        assert!(player.start(&mut beeper, click, Priority::Alarm, |_| Ok(())));
+        assert_eq!(player.next(&mut beeper, generation), None);
+        assert_eq!(player.next(&mut beeper, player.generation()), Some(8));
+    }
+
+    #[test]
+    fn spawn_failure_keeps_the_melody() {
+        let mut beeper = beeper();
+        let mut player = Player::new();
+        assert!(player.start(&mut beeper, sounds::ERROR, Priority::Notify, |_| Ok(())));
+        let generation = player.generation();
+        assert_eq!(player.next(&mut beeper, generation), Some(150));
+
+        // task queue full
+        assert!(!player.start(&mut beeper, sounds::ALARM, Priority::Alarm, Err));
+        assert_eq!(player.generation(), generation);
+        assert!(player.is_busy(&beeper));
+        assert_eq!(player.next(&mut beeper, generation), Some(50));
+        assert_eq!(player.next(&mut beeper, generation), Some(300));
+        assert_eq!(player.next(&mut beeper, generation), None);
+        assert!(!player.is_busy(&beeper));
+    }
+}
@@ -0,0 +1,199 @@
+//
+// AT24Cxx I2C EEPROM
+//
+// https://ww1.microchip.com/downloads/en/DeviceDoc/AT24C04C-AT24C08C-I2C-Compatible-Two-Wire-Serial-EEPROM-4-Kbit-8-Kbit-20006127A.pdf
+//
+// Small parts (up to AT24C16) take a one byte word address, the bits above
+// it go into the low bits of the device address (A0..A2 are unconnected then).
+// From AT24C32 on the word address is two bytes.
+//
+// Writes go a page at a time and wrap around within the page, they're split
+// here. The chip doesn't answer while it writes (about 5ms), the next access
+// polls for its ACK.
+//
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+/// 0b1010 A2 A1 A0
+pub const BASE_ADDRESS: u8 = 0x50;
+
+/// Device address polls while a write cycle runs, each one a NACKed
+/// address byte (~0.1ms at 100kHz), well past the 5ms tWR
+const WRITE_POLLS: u32 = 200;
+
+/// Largest page, bytes written in one go
+const MAX_PAGE: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub enum EepromError<E> {
+    I2c(E),
+    /// Past the end of the memory
+    OutOfRange,
+    /// Still busy after a write
+    Timeout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Variant {
+    /// Bytes
+    pub size: u32,
+    /// Page write size
+    pub page: u16,
+    /// Two byte word address
+    pub wide: bool,
+}
+
+pub const AT24C02: Variant = Variant {
+    size: 256,
+    page: 8,
+    wide: false,
+};
+
+pub const AT24C04: Variant = Variant {
+    size: 512,
+    page: 16,
+    wide: false,
+};
+
+pub const AT24C08: Variant = Variant {
+    size: 1024,
+    page: 16,
+    wide: false,
+};
+
+pub const AT24C16: Variant = Variant {
+    size: 2048,
+    page: 16,
+    wide: false,
+};
+
+pub const AT24C32: Variant = Variant {
+    size: 4096,
+    page: 32,
+    wide: true,
+};
+
+pub const AT24C64: Variant = Variant {
+    size: 8192,
+    page: 32,
+    wide: true,
+};
+
+pub struct At24c<I2C> {
+    i2c: I2C,
+    variant: Variant,
+    /// A2..A0 as strapped, 0 on small parts
+    address: u8,
+}
+
+impl<I2C, E> At24c<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// `address` is what A2..A0 are strapped to, ignored on parts that
+    /// use those bits for the word address
+    pub fn new(i2c: I2C, variant: Variant, address: u8) -> Self {
+        At24c {
+            i2c,
+            variant,
+            address: if variant.wide { address & 0b111 } else { 0 },
+        }
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn size(&self) -> u32 {
+        self.variant.size
+    }
+
+    /// Fills `buf` from `offset` on
+    pub fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), EepromError<E>> {
+        self.check(offset, buf.len())?;
+
+        // small parts only read on within the 256 byte block the device address picks
+        let mut offset = offset;
+        let mut buf = buf;
+        while !buf.is_empty() {
+            let n = if self.variant.wide {
+                buf.len()
+            } else {
+                buf.len().min(256 - (offset as usize & 0xff))
+            };
+            let (chunk, rest) = buf.split_at_mut(n);
+
+            let (device, word, len) = self.word_address(offset);
+            self.poll(device, &word[..len])?;
+            self.i2c
+                .write_read(device, &word[..len], chunk)
+                .map_err(EepromError::I2c)?;
+
+            offset += n as u32;
+            buf = rest;
+        }
+        Ok(())
+    }
+
+    /// Writes `data` at `offset`, a page at a time, returns once the last write cycle finished
+    pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), EepromError<E>> {
+        self.check(offset, data.len())?;
+
+        // a smaller split is still within the page
+        let page = (self.variant.page as usize).min(MAX_PAGE);
+        let mut offset = offset;
+        let mut data = data;
+        let mut frame = [0u8; MAX_PAGE + 2];
+        while !data.is_empty() {
+            let n = data.len().min(page - offset as usize % page);
+            let (chunk, rest) = data.split_at(n);
+
+            let (device, word, len) = self.word_address(offset);
+            frame[..len].copy_from_slice(&word[..len]);
+            frame[len..len + n].copy_from_slice(chunk);
+            self.poll(device, &word[..len])?;
+            self.i2c
+                .write(device, &frame[..len + n])
+                .map_err(EepromError::I2c)?;
+
+            offset += n as u32;
+            data = rest;
+        }
+
+        // done when it answers again
+        let (device, word, len) = self.word_address(0);
+        self.poll(device, &word[..len])
+    }
+
+    /// Gives back the bus
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    fn check(&self, offset: u32, len: usize) -> Result<(), EepromError<E>> {
+        if offset as u64 + len as u64 > self.variant.size as u64 {
+            return Err(EepromError::OutOfRange);
+        }
+        Ok(())
+    }
+
+    /// Device address and word address bytes for `offset`
+    fn word_address(&self, offset: u32) -> (u8, [u8; 2], usize) {
+        if self.variant.wide {
+            let device = BASE_ADDRESS | self.address;
+            (device, [(offset >> 8) as u8, offset as u8], 2)
+        } else {
+            let device = BASE_ADDRESS | ((offset >> 8) as u8 & 0b111);
+            (device, [offset as u8, 0], 1)
+        }
+    }
+
+    /// Waits out a write cycle: sets the word address until the chip ACKs it
+    fn poll(&mut self, device: u8, word: &[u8]) -> Result<(), EepromError<E>> {
+        for _ in 0..WRITE_POLLS {
+            if self.i2c.write(device, word).is_ok() {
+                return Ok(());
+            }
+        }
+        Err(EepromError::Timeout)
+    }
+}
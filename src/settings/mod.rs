@@ -0,0 +1,105 @@
+//
+// Settings kept over reboots, in the I2C EEPROM
+//
+// `Settings` is encoded field after field into a payload that only ever grows:
+// fields are appended, never moved. A record written by older firmware is
+// shorter, the fields it lacks keep their defaults and the store rewrites it
+// in the current layout (`store::Store::load`); newer records are read as
+// far as this firmware knows them. Changes that can't be expressed by appending
+// bump `VERSION` and get a case in `Settings::decode`.
+//
+//   let mut store = Store::new(At24c::new(board.eeprom, EEPROM_VARIANT, 0), 0, EEPROM_VARIANT.size)?;
+//   let (mut settings, _) = store.load()?;
+//   settings.rotation = Rotation::R90;
+//   store.save(&settings)?;
+//
+pub mod at24c;
+pub mod store;
+
+pub use at24c::{At24c, EepromError, Variant};
+pub use store::{SettingsError, Source, Store};
+
+use core::convert::TryFrom;
+
+use crate::{
+    board::SERIAL_BAUD,
+    lcd::Rotation,
+    touch::{calibration, Calibration},
+};
+
+/// Payload layout, bumped for changes other than appended fields
+pub const VERSION: u8 = 1;
+
+/// Payload bytes of the current layout
+pub const PAYLOAD_LEN: usize = CALIBRATION + calibration::SIZE + 1 + 1 + 4;
+
+/// Field offsets, version 1
+const CALIBRATION: usize = 0;
+const BRIGHTNESS: usize = CALIBRATION + calibration::SIZE;
+const ROTATION: usize = BRIGHTNESS + 1;
+const BAUD: usize = ROTATION + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// Touch panel to screen, see the calibrate binary
+    pub calibration: Calibration,
+    /// Backlight, percent, 0 is off. The backlight only switches, anything else is on
+    pub brightness: u8,
+    /// Display, the touch mapping follows it
+    pub rotation: Rotation,
+    /// USART1, the terminal binary
+    pub baud: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            calibration: Calibration::default(),
+            brightness: 100,
+            rotation: Rotation::R0,
+            baud: SERIAL_BAUD,
+        }
+    }
+}
+
+impl Settings {
+    pub fn encode(&self) -> [u8; PAYLOAD_LEN] {
+        let mut payload = [0u8; PAYLOAD_LEN];
+        payload[CALIBRATION..BRIGHTNESS].copy_from_slice(&self.calibration.to_bytes());
+        payload[BRIGHTNESS] = self.brightness;
+        payload[ROTATION] = self.rotation as u8;
+        payload[BAUD..BAUD + 4].copy_from_slice(&self.baud.to_le_bytes());
+        payload
+    }
+
+    /// `payload` as written in layout `version`,
+    /// fields missing from it or out of range keep their defaults
+    pub fn decode(version: u8, payload: &[u8]) -> Self {
+        let mut settings = Settings::default();
+        // there's no layout before 1, from 1 on the fields stay where they were
+        if version == 0 {
+            return settings;
+        }
+
+        if let Some(bytes) = payload.get(CALIBRATION..BRIGHTNESS) {
+            if let Ok(c) = Calibration::from_bytes(bytes) {
+                settings.calibration = c;
+            }
+        }
+        if let Some(b) = payload.get(BRIGHTNESS) {
+            settings.brightness = (*b).min(100);
+        }
+        if let Some(r) = payload.get(ROTATION) {
+            if let Ok(r) = Rotation::try_from(*r as u32) {
+                settings.rotation = r;
+            }
+        }
+        if let Some(bytes) = payload.get(BAUD..BAUD + 4) {
+            let baud = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            if baud > 0 {
+                settings.baud = baud;
+            }
+        }
+        settings
+    }
+}
@@ -0,0 +1,396 @@
+//
+// Settings records in a region of the EEPROM
+//
+// The region is cut into slots, every save goes to the slot after the newest
+// record with the next sequence number, so writes spread over all of them
+// (AT24Cxx cells are good for about a million). Saves that change nothing
+// aren't written at all.
+//
+// Record, little endian:
+//
+//   0  "ST"
+//   2  layout version
+//   3  payload length
+//   4  sequence number, u32
+//   8  payload
+//      CRC-16/CCITT-FALSE of all of the above
+//
+// A write cut short by a reset fails its CRC and the record before it stays
+// the newest one. No valid record at all (blank chip, or all of them corrupt)
+// loads the defaults.
+//
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use super::{
+    at24c::{At24c, EepromError},
+    Settings, PAYLOAD_LEN, VERSION,
+};
+
+/// Bytes per record slot
+pub const SLOT_LEN: u32 = 64;
+
+const MAGIC: [u8; 2] = *b"ST";
+const HEADER_LEN: usize = 8;
+const CRC_LEN: usize = 2;
+
+/// Longest payload a slot holds
+const MAX_PAYLOAD: usize = SLOT_LEN as usize - HEADER_LEN - CRC_LEN;
+
+// the current layout has to fit
+const _: [(); 1] = [(); (PAYLOAD_LEN <= MAX_PAYLOAD) as usize];
+
+#[derive(Debug, Clone, Copy)]
+pub enum SettingsError<E> {
+    Eeprom(EepromError<E>),
+    /// Region outside the EEPROM or smaller than a slot
+    Region,
+    /// Read back different from what was written, the slot may be worn out
+    Verify,
+}
+
+impl<E> From<EepromError<E>> for SettingsError<E> {
+    fn from(e: EepromError<E>) -> Self {
+        SettingsError::Eeprom(e)
+    }
+}
+
+/// Where loaded settings came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    /// No valid record
+    Defaults,
+    /// A record in the current layout, or a newer one
+    Record,
+    /// A record in an older layout, or one lacking fields appended since,
+    /// rewritten in the current one
+    Migrated { from: u8 },
+}
+
+pub struct Store<I2C> {
+    eeprom: At24c<I2C>,
+    start: u32,
+    slots: u32,
+    /// Slot and sequence number of the newest record
+    newest: Option<(u32, u32)>,
+    /// What that record holds
+    current: Option<Settings>,
+}
+
+impl<I2C, E> Store<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// Records go into `len` bytes from `start` on, whole slots of them
+    pub fn new(eeprom: At24c<I2C>, start: u32, len: u32) -> Result<Self, SettingsError<E>> {
+        let slots = len / SLOT_LEN;
+        if slots == 0 || start as u64 + len as u64 > eeprom.size() as u64 {
+            return Err(SettingsError::Region);
+        }
+        Ok(Store {
+            eeprom,
+            start,
+            slots,
+            newest: None,
+            current: None,
+        })
+    }
+
+    /// Newest valid record, or the defaults. Records in an older layout, or
+    /// shorter than the current one, are saved again in the current one.
+    pub fn load(&mut self) -> Result<(Settings, Source), SettingsError<E>> {
+        self.newest = None;
+        self.current = None;
+
+        let mut best: Option<(u32, u32, u8, usize)> = None;
+        let mut best_settings = Settings::default();
+        let mut record = [0u8; SLOT_LEN as usize];
+        for slot in 0..self.slots {
+            self.eeprom.read(self.offset(slot), &mut record)?;
+            let (version, sequence, payload) = match parse(&record) {
+                Some(r) => r,
+                None => continue,
+            };
+            let newer = match best {
+                None => true,
+                Some((_, s, _, _)) => sequence.wrapping_sub(s) as i32 > 0,
+            };
+            if newer {
+                best = Some((slot, sequence, version, payload.len()));
+                best_settings = Settings::decode(version, payload);
+            }
+        }
+
+        let (slot, sequence, version, len) = match best {
+            Some(b) => b,
+            None => return Ok((Settings::default(), Source::Defaults)),
+        };
+        self.newest = Some((slot, sequence));
+        self.current = Some(best_settings);
+
+        // fields appended since were left out by older firmware
+        if version < VERSION || (version == VERSION && len < PAYLOAD_LEN) {
+            // `current` is what the old record decodes to, force the write
+            self.current = None;
+            self.save(&best_settings)?;
+            return Ok((best_settings, Source::Migrated { from: version }));
+        }
+        Ok((best_settings, Source::Record))
+    }
+
+    /// Writes `settings` into the next slot, false when they're what's stored already
+    pub fn save(&mut self, settings: &Settings) -> Result<bool, SettingsError<E>> {
+        if self.current.as_ref() == Some(settings) {
+            return Ok(false);
+        }
+
+        let (slot, sequence) = match self.newest {
+            Some((slot, sequence)) => ((slot + 1) % self.slots, sequence.wrapping_add(1)),
+            None => (0, 1),
+        };
+
+        let payload = settings.encode();
+        let len = HEADER_LEN + PAYLOAD_LEN;
+        let mut record = [0u8; SLOT_LEN as usize];
+        record[0..2].copy_from_slice(&MAGIC);
+        record[2] = VERSION;
+        record[3] = PAYLOAD_LEN as u8;
+        record[4..8].copy_from_slice(&sequence.to_le_bytes());
+        record[HEADER_LEN..len].copy_from_slice(&payload);
+        let crc = crc16(&record[..len]);
+        record[len..len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+        let offset = self.offset(slot);
+        let record = &record[..len + CRC_LEN];
+        self.eeprom.write(offset, record)?;
+
+        let mut check = [0u8; SLOT_LEN as usize];
+        let check = &mut check[..record.len()];
+        self.eeprom.read(offset, check)?;
+        if check != record {
+            return Err(SettingsError::Verify);
+        }
+
+        self.newest = Some((slot, sequence));
+        self.current = Some(*settings);
+        Ok(true)
+    }
+
+    /// Gives back the EEPROM
+    pub fn release(self) -> At24c<I2C> {
+        self.eeprom
+    }
+
+    fn offset(&self, slot: u32) -> u32 {
+        self.start + slot * SLOT_LEN
+    }
+}
+
+/// Version, sequence number and payload of a valid record
+fn parse(record: &[u8]) -> Option<(u8, u32, &[u8])> {
+    if record[0..2] != MAGIC {
+        return None;
+    }
+    let version = record[2];
+    let len = record[3] as usize;
+    if len > MAX_PAYLOAD {
+        return None;
+    }
+    let end = HEADER_LEN + len;
+    let crc = u16::from_le_bytes([record[end], record[end + 1]]);
+    if crc != crc16(&record[..end]) {
+        return None;
+    }
+    let sequence = u32::from_le_bytes([record[4], record[5], record[6], record[7]]);
+    Some((version, sequence, &record[HEADER_LEN..end]))
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, starts at 0xffff
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |crc, b| {
+        (0..8).fold(crc ^ ((*b as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lcd::Rotation,
+        settings::at24c::{Variant, AT24C02},
+        touch::calibration,
+    };
+
+    /// AT24C02: 4 slots
+    const CHIP: Variant = AT24C02;
+
+    /// Memory behind a one byte word address, writes wrap within the page
+    struct Eeprom {
+        memory: [u8; 256],
+    }
+
+    impl Write for Eeprom {
+        type Error = ();
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ()> {
+            let page = CHIP.page as usize;
+            let start = ((address as usize & 0b111) << 8 | bytes[0] as usize) % CHIP.size as usize;
+            for (i, b) in bytes[1..].iter().enumerate() {
+                let offset = start - start % page + (start + i) % page;
+                self.memory[offset] = *b;
+            }
+            Ok(())
+        }
+    }
+
+    impl WriteRead for Eeprom {
+        type Error = ();
+
+        fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            let start = (address as usize & 0b111) << 8 | bytes[0] as usize;
+            buffer.copy_from_slice(&self.memory[start..start + buffer.len()]);
+            Ok(())
+        }
+    }
+
+    fn store(memory: [u8; 256]) -> Store<Eeprom> {
+        Store::new(At24c::new(Eeprom { memory }, CHIP, 0), 0, CHIP.size).unwrap()
+    }
+
+    fn contents(store: Store<Eeprom>) -> [u8; 256] {
+        store.release().release().memory
+    }
+
+    /// A valid record for `slot`
+    fn record(memory: &mut [u8; 256], slot: usize, version: u8, sequence: u32, payload: &[u8]) {
+        let end = HEADER_LEN + payload.len();
+        let record = &mut memory[slot * SLOT_LEN as usize..][..end + CRC_LEN];
+        record[0..2].copy_from_slice(&MAGIC);
+        record[2] = version;
+        record[3] = payload.len() as u8;
+        record[4..8].copy_from_slice(&sequence.to_le_bytes());
+        record[HEADER_LEN..end].copy_from_slice(payload);
+        let crc = crc16(&record[..end]);
+        record[end..].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    fn sequence(memory: &[u8; 256], slot: usize) -> u32 {
+        let at = slot * SLOT_LEN as usize + 4;
+        u32::from_le_bytes([memory[at], memory[at + 1], memory[at + 2], memory[at + 3]])
+    }
+
+    fn settings(brightness: u8) -> Settings {
+        Settings {
+            brightness,
+            rotation: Rotation::R90,
+            baud: 9600,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+        assert_eq!(crc16(&[]), 0xffff);
+    }
+
+    #[test]
+    fn saves_round_robin() {
+        let mut s = store([0xff; 256]);
+        assert_eq!(s.load().unwrap(), (Settings::default(), Source::Defaults));
+
+        for (i, b) in (10..60).step_by(10).enumerate() {
+            assert!(s.save(&settings(b)).unwrap());
+            // nothing changed, nothing written
+            assert!(!s.save(&settings(b)).unwrap());
+            assert_eq!(s.newest, Some((i as u32 % 4, i as u32 + 1)));
+        }
+
+        let memory = contents(s);
+        // the fifth save went over the first one
+        let sequences: Vec<u32> = (0..4).map(|slot| sequence(&memory, slot)).collect();
+        assert_eq!(sequences, [5, 2, 3, 4]);
+
+        let mut s = store(memory);
+        assert_eq!(s.load().unwrap(), (settings(50), Source::Record));
+    }
+
+    #[test]
+    fn sequence_wraps_around() {
+        let mut memory = [0xff; 256];
+        record(
+            &mut memory,
+            1,
+            VERSION,
+            u32::MAX - 1,
+            &settings(10).encode(),
+        );
+        record(&mut memory, 2, VERSION, u32::MAX, &settings(20).encode());
+        record(&mut memory, 3, VERSION, 0, &settings(30).encode());
+
+        let mut s = store(memory);
+        assert_eq!(s.load().unwrap(), (settings(30), Source::Record));
+        assert!(s.save(&settings(40)).unwrap());
+
+        let memory = contents(s);
+        assert_eq!(sequence(&memory, 0), 1);
+        assert_eq!(
+            store(memory).load().unwrap(),
+            (settings(40), Source::Record)
+        );
+    }
+
+    #[test]
+    fn corrupt_record_falls_back() {
+        let mut s = store([0xff; 256]);
+        s.save(&settings(10)).unwrap();
+        s.save(&settings(20)).unwrap();
+
+        // a byte of the newest payload, as a write cut short would leave it
+        let mut memory = contents(s);
+        memory[SLOT_LEN as usize + HEADER_LEN + 2] ^= 0x01;
+        let mut s = store(memory);
+        assert_eq!(s.load().unwrap(), (settings(10), Source::Record));
+
+        // the next save goes after the record that's still good
+        assert!(s.save(&settings(30)).unwrap());
+        let mut memory = contents(s);
+        assert_eq!(sequence(&memory, 1), 2);
+
+        memory[HEADER_LEN] ^= 0x80;
+        memory[SLOT_LEN as usize + 3] = 0xee;
+        assert_eq!(
+            store(memory).load().unwrap(),
+            (Settings::default(), Source::Defaults)
+        );
+    }
+
+    #[test]
+    fn migrates_short_records() {
+        // a version 1 record from before rotation and baud were appended
+        let full = settings(30).encode();
+        let mut memory = [0xff; 256];
+        record(&mut memory, 2, VERSION, 7, &full[..calibration::SIZE + 1]);
+
+        let expected = Settings {
+            brightness: 30,
+            ..Settings::default()
+        };
+        let mut s = store(memory);
+        assert_eq!(
+            s.load().unwrap(),
+            (expected, Source::Migrated { from: VERSION })
+        );
+
+        // rewritten in the current layout, into the next slot
+        let memory = contents(s);
+        assert_eq!(sequence(&memory, 3), 8);
+        assert_eq!(memory[3 * SLOT_LEN as usize + 3] as usize, PAYLOAD_LEN);
+        assert_eq!(store(memory).load().unwrap(), (expected, Source::Record));
+    }
+}
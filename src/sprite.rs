@@ -0,0 +1,181 @@
+//
+// Software sprites (pointer, crosshair, dragged items) on top of the LCD.
+// Background under the sprite is saved with a GRAM readback and put back
+// when the sprite moves or hides, so nothing underneath gets destroyed.
+//
+use embedded_graphics::{
+    geometry::{Point, Size},
+    pixelcolor::Rgb565,
+    DrawTarget,
+};
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use crate::lcd::{Lcd, LcdError, Rotation};
+
+#[derive(Debug, Clone, Copy)]
+pub enum SpriteError {
+    /// Background buffer is smaller than the sprite
+    BufferSize,
+    Lcd(LcdError),
+}
+
+impl From<LcdError> for SpriteError {
+    fn from(e: LcdError) -> Self {
+        SpriteError::Lcd(e)
+    }
+}
+
+/// Rgb565 image with an optional transparent key color
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite<'a> {
+    size: Size,
+    pixels: &'a [Rgb565],
+    key: Option<Rgb565>,
+    /// Point of the sprite that lands on the position, e.g. crosshair center
+    hotspot: Point,
+}
+
+impl<'a> Sprite<'a> {
+    /// `pixels` go row by row, `width * height` of them
+    pub fn new(size: Size, pixels: &'a [Rgb565], key: Option<Rgb565>) -> Self {
+        Sprite {
+            size,
+            pixels,
+            key,
+            hotspot: Point::zero(),
+        }
+    }
+
+    pub fn with_hotspot(self, hotspot: Point) -> Self {
+        Sprite { hotspot, ..self }
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> Option<Rgb565> {
+        let c = *self.pixels.get((y * self.size.width + x) as usize)?;
+        if Some(c) == self.key {
+            None
+        } else {
+            Some(c)
+        }
+    }
+}
+
+/// On-screen area covered by a sprite, clipped
+#[derive(Debug, Clone, Copy)]
+struct Covered {
+    top_left: Point,
+    size: Size,
+    rotation: Rotation,
+}
+
+/// One sprite on the screen plus the background it covers.
+/// Coordinates are in the rotated screen space, `Lcd` windows map them to GRAM.
+pub struct SpriteLayer<'b> {
+    background: &'b mut [Rgb565],
+    covered: Option<Covered>,
+}
+
+impl<'b> SpriteLayer<'b> {
+    /// `background` has to hold as many pixels as the largest sprite shown
+    pub fn new(background: &'b mut [Rgb565]) -> Self {
+        SpriteLayer {
+            background,
+            covered: None,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.covered.is_some()
+    }
+
+    /// Shows `sprite` with its hotspot at `position`, moving it if already shown
+    pub fn show<D>(
+        &mut self,
+        lcd: &mut Lcd<D>,
+        sprite: &Sprite,
+        position: Point,
+    ) -> Result<(), SpriteError>
+    where
+        D: DelayMs<u16> + DelayUs<u16>,
+    {
+        self.hide(lcd)?;
+
+        let top_left = position - sprite.hotspot;
+        let Size { width, height } = lcd.size();
+
+        // clip to the screen
+        let x0 = top_left.x.max(0);
+        let y0 = top_left.y.max(0);
+        let x1 = (top_left.x + sprite.size.width as i32).min(width as i32);
+        let y1 = (top_left.y + sprite.size.height as i32).min(height as i32);
+        if x0 >= x1 || y0 >= y1 {
+            return Ok(());
+        }
+
+        let visible = Point::new(x0, y0);
+        let size = Size::new((x1 - x0) as u32, (y1 - y0) as u32);
+        let n = (size.width * size.height) as usize;
+        if self.background.len() < n {
+            return Err(SpriteError::BufferSize);
+        }
+
+        lcd.read_pixels(visible, size, &mut self.background[..n])?;
+
+        // sprite pixels where opaque, saved background where transparent
+        let offset = visible - top_left;
+        let background = &self.background[..n];
+        let pixels = background.iter().enumerate().map(|(i, bg)| {
+            let x = i as u32 % size.width + offset.x as u32;
+            let y = i as u32 / size.width + offset.y as u32;
+            sprite.pixel(x, y).unwrap_or(*bg)
+        });
+        lcd.write_pixels(visible, size, pixels)?;
+
+        self.covered = Some(Covered {
+            top_left: visible,
+            size,
+            rotation: lcd.rotation(),
+        });
+        Ok(())
+    }
+
+    /// Puts the saved background back.
+    /// Works after a rotation change too, the background goes back where it was taken from.
+    pub fn hide<D>(&mut self, lcd: &mut Lcd<D>) -> Result<(), SpriteError>
+    where
+        D: DelayMs<u16> + DelayUs<u16>,
+    {
+        let covered = match self.covered.take() {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+
+        let rotation = lcd.rotation();
+        if rotation != covered.rotation {
+            lcd.set_rotation(covered.rotation)?;
+        }
+
+        let n = (covered.size.width * covered.size.height) as usize;
+        let res = lcd.write_pixels(
+            covered.top_left,
+            covered.size,
+            self.background[..n].iter().copied(),
+        );
+
+        if rotation != covered.rotation {
+            lcd.set_rotation(rotation)?;
+        }
+
+        Ok(res?)
+    }
+
+    /// Forgets the saved background without drawing it, e.g. after the screen got redrawn
+    pub fn discard(&mut self) {
+        self.covered = None;
+    }
+}
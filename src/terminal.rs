@@ -0,0 +1,741 @@
+//
+// VT100 / ANSI terminal emulator.
+//
+// Parses a byte stream (typically from a UART) and keeps a character grid,
+// dirty rows are re-rendered with a monospace font on any DrawTarget<Rgb565>.
+//
+// https://vt100.net/docs/vt100-ug/chapter3.html
+// https://en.wikipedia.org/wiki/ANSI_escape_code
+//
+use embedded_graphics::{
+    fonts::{Font6x8, Text},
+    geometry::{Point, Size},
+    pixelcolor::{Rgb565, Rgb888},
+    prelude::*,
+    style::TextStyleBuilder,
+    DrawTarget,
+};
+
+const CHAR_WIDTH: usize = 6;
+const CHAR_HEIGHT: usize = 8;
+
+/// Enough cells to cover the panel in either orientation.
+const MAX_COLS: usize = 320 / CHAR_WIDTH;
+const MAX_ROWS: usize = 320 / CHAR_HEIGHT;
+
+const MAX_PARAMS: usize = 8;
+const TAB_WIDTH: usize = 8;
+
+const DEFAULT_FG: u8 = 7;
+const DEFAULT_BG: u8 = 0;
+
+/// Standard VGA-ish 16 color palette
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (170, 0, 0),
+    (0, 170, 0),
+    (170, 85, 0),
+    (0, 0, 170),
+    (170, 0, 170),
+    (0, 170, 170),
+    (170, 170, 170),
+    (85, 85, 85),
+    (255, 85, 85),
+    (85, 255, 85),
+    (255, 255, 85),
+    (85, 85, 255),
+    (255, 85, 255),
+    (85, 255, 255),
+    (255, 255, 255),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Attr {
+    fg: u8,
+    bg: u8,
+    bold: bool,
+    inverse: bool,
+}
+
+impl Default for Attr {
+    fn default() -> Self {
+        Attr {
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
+            bold: false,
+            inverse: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    ch: u8,
+    attr: Attr,
+}
+
+impl Cell {
+    fn blank(attr: Attr) -> Self {
+        Cell {
+            ch: b' ',
+            attr: Attr {
+                bold: false,
+                inverse: false,
+                ..attr
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    /// ESC ( / ESC ) charset designation, next byte is ignored
+    Charset,
+    Csi,
+}
+
+/// Terminal emulator state: character grid, cursor and escape sequence parser.
+pub struct Terminal {
+    cells: [[Cell; MAX_COLS]; MAX_ROWS],
+    dirty: [bool; MAX_ROWS],
+    cols: usize,
+    rows: usize,
+
+    cx: usize,
+    cy: usize,
+    attr: Attr,
+    saved: (usize, usize, Attr),
+
+    /// Scrolling region, inclusive
+    top: usize,
+    bottom: usize,
+
+    /// Cursor sits past the last column, next printable wraps first
+    wrap_pending: bool,
+    autowrap: bool,
+    cursor_visible: bool,
+    drawn_cursor: Option<(usize, usize)>,
+    bell: bool,
+
+    state: State,
+    params: [u16; MAX_PARAMS],
+    nparams: usize,
+    private: bool,
+
+    palette: [Rgb565; 16],
+}
+
+impl Terminal {
+    /// New terminal filling a screen of the given size
+    pub fn new(size: Size) -> Self {
+        let mut palette = [Rgb565::BLACK; 16];
+        for (c, &(r, g, b)) in palette.iter_mut().zip(PALETTE.iter()) {
+            *c = Rgb888::new(r, g, b).into();
+        }
+
+        let mut term = Terminal {
+            cells: [[Cell::blank(Attr::default()); MAX_COLS]; MAX_ROWS],
+            dirty: [true; MAX_ROWS],
+            cols: 1,
+            rows: 1,
+            cx: 0,
+            cy: 0,
+            attr: Attr::default(),
+            saved: (0, 0, Attr::default()),
+            top: 0,
+            bottom: 0,
+            wrap_pending: false,
+            autowrap: true,
+            cursor_visible: true,
+            drawn_cursor: None,
+            bell: false,
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            nparams: 0,
+            private: false,
+            palette,
+        };
+        term.resize(size);
+        term
+    }
+
+    /// Adjusts the grid to a new screen size (e.g. after rotation), keeps the content
+    /// that still fits and redraws everything.
+    pub fn resize(&mut self, size: Size) {
+        self.cols = (size.width as usize / CHAR_WIDTH).clamp(1, MAX_COLS);
+        self.rows = (size.height as usize / CHAR_HEIGHT).clamp(1, MAX_ROWS);
+        self.top = 0;
+        self.bottom = self.rows - 1;
+        self.cx = self.cx.min(self.cols - 1);
+        self.cy = self.cy.min(self.rows - 1);
+        self.wrap_pending = false;
+        self.drawn_cursor = None;
+        self.dirty = [true; MAX_ROWS];
+    }
+
+    /// Grid size, (columns, rows)
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    /// Cursor position, (column, row), zero based
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.cx, self.cy)
+    }
+
+    /// Returns true once for every BEL received
+    pub fn take_bell(&mut self) -> bool {
+        core::mem::replace(&mut self.bell, false)
+    }
+
+    /// Feeds a slice of bytes through the parser
+    pub fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.write_byte(*b);
+        }
+    }
+
+    /// Feeds one byte through the parser
+    pub fn write_byte(&mut self, b: u8) {
+        // C0 controls are executed in the middle of escape sequences too
+        match b {
+            0x18 | 0x1a => {
+                self.state = State::Ground;
+                return;
+            }
+            0x1b => {
+                self.state = State::Escape;
+                return;
+            }
+            0x00..=0x1f => {
+                self.control(b);
+                return;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => self.print(b),
+            State::Escape => self.escape(b),
+            State::Charset => self.state = State::Ground,
+            State::Csi => self.csi(b),
+        }
+    }
+
+    /// Renders all dirty rows
+    pub fn render<T>(&mut self, target: &mut T) -> Result<(), T::Error>
+    where
+        T: DrawTarget<Rgb565>,
+    {
+        while self.render_next(target)? {}
+        Ok(())
+    }
+
+    /// Renders the first dirty row, returns false when there was nothing to do.
+    /// Lets the caller release shared state between rows.
+    pub fn render_next<T>(&mut self, target: &mut T) -> Result<bool, T::Error>
+    where
+        T: DrawTarget<Rgb565>,
+    {
+        let cursor = self.visible_cursor();
+        if cursor != self.drawn_cursor {
+            for (_, row) in self.drawn_cursor.iter().chain(cursor.iter()) {
+                self.dirty[*row] = true;
+            }
+            self.drawn_cursor = cursor;
+        }
+
+        match self.dirty[..self.rows].iter().position(|d| *d) {
+            Some(row) => {
+                self.dirty[row] = false;
+                self.draw_row(target, row)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn draw_row<T>(&self, target: &mut T, row: usize) -> Result<(), T::Error>
+    where
+        T: DrawTarget<Rgb565>,
+    {
+        let mut text = [0u8; MAX_COLS];
+        let y = (row * CHAR_HEIGHT) as i32;
+
+        // one Text per run of identically colored cells
+        let mut col = 0;
+        while col < self.cols {
+            let start = col;
+            let colors = self.cell_colors(row, col);
+            while col < self.cols && self.cell_colors(row, col) == colors {
+                text[col] = self.cells[row][col].ch;
+                col += 1;
+            }
+
+            let (fg, bg) = colors;
+            let style = TextStyleBuilder::new(Font6x8)
+                .text_color(fg)
+                .background_color(bg)
+                .build();
+
+            // cells only ever hold printable ASCII
+            let s = core::str::from_utf8(&text[start..col]).unwrap_or("?");
+            Text::new(s, Point::new((start * CHAR_WIDTH) as i32, y))
+                .into_styled(style)
+                .draw(target)?;
+        }
+
+        Ok(())
+    }
+
+    fn cell_colors(&self, row: usize, col: usize) -> (Rgb565, Rgb565) {
+        let attr = self.cells[row][col].attr;
+        let fg = if attr.bold && attr.fg < 8 {
+            attr.fg + 8
+        } else {
+            attr.fg
+        };
+        let (fg, bg) = (self.palette[fg as usize], self.palette[attr.bg as usize]);
+
+        let cursor = self.drawn_cursor == Some((col, row));
+        if attr.inverse != cursor {
+            (bg, fg)
+        } else {
+            (fg, bg)
+        }
+    }
+
+    fn visible_cursor(&self) -> Option<(usize, usize)> {
+        if self.cursor_visible {
+            Some((self.cx, self.cy))
+        } else {
+            None
+        }
+    }
+
+    fn control(&mut self, b: u8) {
+        match b {
+            0x07 => self.bell = true,
+            0x08 => {
+                self.cx = self.cx.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            b'\t' => {
+                self.cx = ((self.cx / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1);
+                self.wrap_pending = false;
+            }
+            b'\n' | 0x0b | 0x0c => self.index(),
+            b'\r' => {
+                self.cx = 0;
+                self.wrap_pending = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, b: u8) {
+        let ch = match b {
+            0x20..=0x7e => b,
+            0x7f => return,
+            // UTF-8 continuation bytes
+            0x80..=0xbf => return,
+            // one placeholder per non-ASCII code point
+            _ => b'?',
+        };
+
+        if self.wrap_pending {
+            self.cx = 0;
+            self.index();
+        }
+
+        self.cells[self.cy][self.cx] = Cell {
+            ch,
+            attr: self.attr,
+        };
+        self.dirty[self.cy] = true;
+
+        if self.cx + 1 < self.cols {
+            self.cx += 1;
+        } else {
+            self.wrap_pending = self.autowrap;
+        }
+    }
+
+    fn escape(&mut self, b: u8) {
+        self.state = State::Ground;
+        match b {
+            b'[' => {
+                self.params = [0; MAX_PARAMS];
+                self.nparams = 0;
+                self.private = false;
+                self.state = State::Csi;
+            }
+            b'(' | b')' => self.state = State::Charset,
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'D' => self.index(),
+            b'E' => {
+                self.cx = 0;
+                self.index();
+            }
+            b'M' => self.reverse_index(),
+            b'c' => self.reset(),
+            _ => {}
+        }
+    }
+
+    fn csi(&mut self, b: u8) {
+        match b {
+            b'0'..=b'9' => {
+                if self.nparams == 0 {
+                    self.nparams = 1;
+                }
+                if let Some(p) = self.params.get_mut(self.nparams - 1) {
+                    *p = p.saturating_mul(10).saturating_add((b - b'0') as u16);
+                }
+            }
+            b';' => {
+                self.nparams = (self.nparams.max(1) + 1).min(MAX_PARAMS);
+            }
+            b'?' => self.private = true,
+            0x40..=0x7e => {
+                self.state = State::Ground;
+                self.dispatch(b);
+            }
+            // other intermediates are not supported
+            _ => {}
+        }
+    }
+
+    /// n-th parameter, with 0 / missing replaced by a default
+    fn param(&self, n: usize, default: u16) -> usize {
+        match self.params[n] {
+            0 => default as usize,
+            p => p as usize,
+        }
+    }
+
+    fn dispatch(&mut self, b: u8) {
+        self.wrap_pending = false;
+        match b {
+            b'A' => {
+                let top = if self.cy >= self.top { self.top } else { 0 };
+                self.cy = self.cy.saturating_sub(self.param(0, 1)).max(top);
+            }
+            b'B' => {
+                let bottom = if self.cy <= self.bottom {
+                    self.bottom
+                } else {
+                    self.rows - 1
+                };
+                self.cy = (self.cy + self.param(0, 1)).min(bottom);
+            }
+            b'C' => self.cx = (self.cx + self.param(0, 1)).min(self.cols - 1),
+            b'D' => self.cx = self.cx.saturating_sub(self.param(0, 1)),
+            b'E' => {
+                self.cx = 0;
+                self.cy = (self.cy + self.param(0, 1)).min(self.rows - 1);
+            }
+            b'F' => {
+                self.cx = 0;
+                self.cy = self.cy.saturating_sub(self.param(0, 1));
+            }
+            b'G' => self.cx = (self.param(0, 1) - 1).min(self.cols - 1),
+            b'd' => self.cy = (self.param(0, 1) - 1).min(self.rows - 1),
+            b'H' | b'f' => {
+                self.cy = (self.param(0, 1) - 1).min(self.rows - 1);
+                self.cx = (self.param(1, 1) - 1).min(self.cols - 1);
+            }
+            b'J' => match self.params[0] {
+                0 => {
+                    self.erase_line(self.cy, self.cx, self.cols);
+                    for row in self.cy + 1..self.rows {
+                        self.erase_line(row, 0, self.cols);
+                    }
+                }
+                1 => {
+                    for row in 0..self.cy {
+                        self.erase_line(row, 0, self.cols);
+                    }
+                    self.erase_line(self.cy, 0, self.cx + 1);
+                }
+                _ => {
+                    for row in 0..self.rows {
+                        self.erase_line(row, 0, self.cols);
+                    }
+                }
+            },
+            b'K' => match self.params[0] {
+                0 => self.erase_line(self.cy, self.cx, self.cols),
+                1 => self.erase_line(self.cy, 0, self.cx + 1),
+                _ => self.erase_line(self.cy, 0, self.cols),
+            },
+            b'X' => {
+                let end = (self.cx + self.param(0, 1)).min(self.cols);
+                self.erase_line(self.cy, self.cx, end);
+            }
+            b'@' => self.insert_chars(self.param(0, 1)),
+            b'P' => self.delete_chars(self.param(0, 1)),
+            b'L' => self.insert_lines(self.param(0, 1)),
+            b'M' => self.delete_lines(self.param(0, 1)),
+            b'S' => self.scroll_up(self.top, self.bottom, self.param(0, 1)),
+            b'T' => self.scroll_down(self.top, self.bottom, self.param(0, 1)),
+            b'm' => self.sgr(),
+            b'r' => {
+                let top = self.param(0, 1) - 1;
+                let bottom = self.param(1, self.rows as u16) - 1;
+                if top < bottom && bottom < self.rows {
+                    self.top = top;
+                    self.bottom = bottom;
+                    self.cx = 0;
+                    self.cy = 0;
+                }
+            }
+            b's' => self.save_cursor(),
+            b'u' => self.restore_cursor(),
+            b'h' | b'l' if self.private => {
+                let on = b == b'h';
+                for i in 0..self.nparams {
+                    match self.params[i] {
+                        7 => self.autowrap = on,
+                        25 => self.cursor_visible = on,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Select Graphic Rendition
+    fn sgr(&mut self) {
+        for i in 0..self.nparams.max(1) {
+            match self.params[i] {
+                0 => self.attr = Attr::default(),
+                1 => self.attr.bold = true,
+                22 => self.attr.bold = false,
+                7 => self.attr.inverse = true,
+                27 => self.attr.inverse = false,
+                p @ 30..=37 => self.attr.fg = (p - 30) as u8,
+                39 => self.attr.fg = DEFAULT_FG,
+                p @ 40..=47 => self.attr.bg = (p - 40) as u8,
+                49 => self.attr.bg = DEFAULT_BG,
+                p @ 90..=97 => self.attr.fg = (p - 90 + 8) as u8,
+                p @ 100..=107 => self.attr.bg = (p - 100 + 8) as u8,
+                _ => {}
+            }
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = (self.cx, self.cy, self.attr);
+    }
+
+    fn restore_cursor(&mut self) {
+        let (cx, cy, attr) = self.saved;
+        self.cx = cx.min(self.cols - 1);
+        self.cy = cy.min(self.rows - 1);
+        self.attr = attr;
+        self.wrap_pending = false;
+    }
+
+    fn reset(&mut self) {
+        self.attr = Attr::default();
+        self.saved = (0, 0, Attr::default());
+        self.top = 0;
+        self.bottom = self.rows - 1;
+        self.cx = 0;
+        self.cy = 0;
+        self.wrap_pending = false;
+        self.autowrap = true;
+        self.cursor_visible = true;
+        for row in 0..self.rows {
+            self.erase_line(row, 0, self.cols);
+        }
+    }
+
+    /// Line feed, scrolls the region when at its bottom
+    fn index(&mut self) {
+        self.wrap_pending = false;
+        if self.cy == self.bottom {
+            self.scroll_up(self.top, self.bottom, 1);
+        } else if self.cy + 1 < self.rows {
+            self.cy += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        self.wrap_pending = false;
+        if self.cy == self.top {
+            self.scroll_down(self.top, self.bottom, 1);
+        } else if self.cy > 0 {
+            self.cy -= 1;
+        }
+    }
+
+    /// Blanks [from, to) columns of a row with the current background
+    fn erase_line(&mut self, row: usize, from: usize, to: usize) {
+        let blank = Cell::blank(self.attr);
+        for c in &mut self.cells[row][from.min(self.cols)..to.min(self.cols)] {
+            *c = blank;
+        }
+        self.dirty[row] = true;
+    }
+
+    /// Moves rows [top, bottom] up by n, blanking the bottom ones
+    fn scroll_up(&mut self, top: usize, bottom: usize, n: usize) {
+        let n = n.min(bottom + 1 - top);
+        for row in top..=bottom {
+            if row + n <= bottom {
+                self.cells[row] = self.cells[row + n];
+                self.dirty[row] = true;
+            } else {
+                self.erase_line(row, 0, self.cols);
+            }
+        }
+    }
+
+    /// Moves rows [top, bottom] down by n, blanking the top ones
+    fn scroll_down(&mut self, top: usize, bottom: usize, n: usize) {
+        let n = n.min(bottom + 1 - top);
+        for row in (top..=bottom).rev() {
+            if row >= top + n {
+                self.cells[row] = self.cells[row - n];
+                self.dirty[row] = true;
+            } else {
+                self.erase_line(row, 0, self.cols);
+            }
+        }
+    }
+
+    fn insert_lines(&mut self, n: usize) {
+        if self.cy >= self.top && self.cy <= self.bottom {
+            self.scroll_down(self.cy, self.bottom, n);
+            self.cx = 0;
+        }
+    }
+
+    fn delete_lines(&mut self, n: usize) {
+        if self.cy >= self.top && self.cy <= self.bottom {
+            self.scroll_up(self.cy, self.bottom, n);
+            self.cx = 0;
+        }
+    }
+
+    fn insert_chars(&mut self, n: usize) {
+        let (row, cols) = (self.cy, self.cols);
+        let n = n.min(cols - self.cx);
+        self.cells[row].copy_within(self.cx..cols - n, self.cx + n);
+        self.erase_line(row, self.cx, self.cx + n);
+    }
+
+    fn delete_chars(&mut self, n: usize) {
+        let (row, cols) = (self.cy, self.cols);
+        let n = n.min(cols - self.cx);
+        self.cells[row].copy_within(self.cx + n..cols, self.cx);
+        self.erase_line(row, cols - n, cols);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 40x30 cells
+    fn term() -> Terminal {
+        Terminal::new(Size::new(240, 240))
+    }
+
+    fn row(term: &Terminal, row: usize) -> [u8; 8] {
+        let mut out = [0; 8];
+        for (o, c) in out.iter_mut().zip(term.cells[row].iter()) {
+            *o = c.ch;
+        }
+        out
+    }
+
+    #[test]
+    fn cursor_moves() {
+        let mut t = term();
+        assert_eq!(t.dimensions(), (40, 30));
+        t.write(b"\x1b[5;10H");
+        assert_eq!(t.cursor(), (9, 4));
+        t.write(b"\x1b[2A\x1b[3C");
+        assert_eq!(t.cursor(), (12, 2));
+        t.write(b"\x1b[10D\x1b[B");
+        assert_eq!(t.cursor(), (2, 3));
+        // clamped to the grid
+        t.write(b"\x1b[99;99H");
+        assert_eq!(t.cursor(), (39, 29));
+        t.write(b"\x1b[H");
+        assert_eq!(t.cursor(), (0, 0));
+        t.write(b"\x1b[7G\x1b[4d");
+        assert_eq!(t.cursor(), (6, 3));
+    }
+
+    #[test]
+    fn scroll_region() {
+        let mut t = term();
+        t.write(b"\x1b[2;4r");
+        assert_eq!((t.top, t.bottom), (1, 3));
+        assert_eq!(t.cursor(), (0, 0));
+
+        t.write(b"\x1b[2Ha\r\nb\r\nc\r\nd");
+        // "a" scrolled out of the region, row 0 and 4 untouched
+        assert_eq!(t.cursor(), (1, 3));
+        assert_eq!(&row(&t, 1)[..1], b"b");
+        assert_eq!(&row(&t, 2)[..1], b"c");
+        assert_eq!(&row(&t, 3)[..1], b"d");
+        assert_eq!(&row(&t, 0)[..1], b" ");
+        assert_eq!(&row(&t, 4)[..1], b" ");
+
+        // a bad region is ignored
+        t.write(b"\x1b[5;2r");
+        assert_eq!((t.top, t.bottom), (1, 3));
+    }
+
+    #[test]
+    fn sgr() {
+        let mut t = term();
+        t.write(b"\x1b[1;31;44m");
+        assert_eq!(
+            t.attr,
+            Attr {
+                fg: 1,
+                bg: 4,
+                bold: true,
+                inverse: false
+            }
+        );
+        t.write(b"\x1b[7;22;92m");
+        assert_eq!((t.attr.fg, t.attr.bold, t.attr.inverse), (10, false, true));
+        t.write(b"\x1b[m");
+        assert_eq!(t.attr, Attr::default());
+        // SGR moves nothing
+        assert_eq!(t.cursor(), (0, 0));
+    }
+
+    #[test]
+    fn wrap_pending() {
+        let mut t = term();
+        t.write(b"\x1b[1;39Hxy");
+        // stays on the last column until the next printable
+        assert_eq!(t.cursor(), (39, 0));
+        assert!(t.wrap_pending);
+        t.write(b"z");
+        assert_eq!(t.cursor(), (1, 1));
+        assert_eq!(&row(&t, 1)[..1], b"z");
+
+        // a cursor move cancels the wrap
+        t.write(b"\x1b[1;40Hx\x1b[D");
+        assert!(!t.wrap_pending);
+        assert_eq!(t.cursor(), (38, 0));
+
+        // no autowrap: overwrites the last column
+        t.write(b"\x1b[?7l\x1b[3;40Hab");
+        assert_eq!(t.cursor(), (39, 2));
+        assert_eq!(t.cells[2][39].ch, b'b');
+    }
+}
@@ -0,0 +1,452 @@
+//
+// Touch panel calibration
+//
+// Raw XPT2046 readings are mapped to LCD native (R0) pixels by an affine
+// transform, solved by least squares from 3 to 5 targets touched by the user,
+// then turned into the current `Rotation`'s screen coordinates. Rotating the
+// display doesn't need a new calibration.
+//
+//   x = (a * raw_x + b * raw_y + c) >> 16
+//   y = (d * raw_x + e * raw_y + f) >> 16
+//
+// Serialized (`to_bytes`) for keeping it across resets, 28 bytes:
+//
+//   "TC", version u8, reserved u8, a, b, c, d, e, f i32 LE
+//
+use core::convert::TryFrom;
+
+use embedded_graphics::{
+    geometry::Point, pixelcolor::Rgb565, prelude::*, primitives::Line, style::PrimitiveStyle,
+    DrawTarget,
+};
+
+use super::xpt2046::{Sample, Touch, MAX_VALUE};
+use crate::lcd::Rotation;
+
+const MAGIC: &[u8; 2] = b"TC";
+const VERSION: u8 = 1;
+
+/// Serialized size
+pub const SIZE: usize = 28;
+
+/// Fewest and most targets
+pub const MIN_POINTS: usize = 3;
+pub const MAX_POINTS: usize = 5;
+
+/// Largest error in pixels a calibration from more than 3 points may leave
+/// at its targets, more means a target was missed
+pub const MAX_ERROR: u32 = 8;
+
+/// Fraction bits of the coefficients
+const SHIFT: u32 = 16;
+
+/// Crosshair arm length
+const ARM: i32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalibrationError {
+    /// Not between MIN_POINTS and MAX_POINTS
+    PointCount,
+    /// Targets on a line or touches all at one spot
+    Degenerate,
+    /// Targets are further than MAX_ERROR pixels from where the solution puts
+    /// their touches, the largest error
+    Inaccurate(u32),
+    BadMagic,
+    Truncated,
+    Unsupported,
+}
+
+/// Raw reading to LCD native coordinates, 16.16 fixed point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    a: i32,
+    b: i32,
+    c: i32,
+    d: i32,
+    e: i32,
+    f: i32,
+}
+
+impl Default for Calibration {
+    /// Uncalibrated: the full raw range stretched over the display
+    fn default() -> Self {
+        let size = Rotation::native_size();
+        let range = MAX_VALUE as i32 + 1;
+        Calibration {
+            a: ((size.width as i32) << SHIFT) / range,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: ((size.height as i32) << SHIFT) / range,
+            f: 0,
+        }
+    }
+}
+
+impl Calibration {
+    /// Least squares fit of raw readings to native points, exact for 3 points.
+    /// `points` are (raw, native) pairs.
+    pub fn solve(points: &[(Point, Point)]) -> Result<Self, CalibrationError> {
+        let n = points.len() as i64;
+        if !(MIN_POINTS..=MAX_POINTS).contains(&points.len()) {
+            return Err(CalibrationError::PointCount);
+        }
+
+        // centered on the means the normal equations are 2x2, and the sums stay in i64
+        let (mut sx, mut sy, mut su, mut sv) = (0i64, 0i64, 0i64, 0i64);
+        for (raw, native) in points {
+            sx += raw.x as i64;
+            sy += raw.y as i64;
+            su += native.x as i64;
+            sv += native.y as i64;
+        }
+
+        // n times the (co)variances
+        let (mut xx, mut yy, mut xy, mut xu, mut yu, mut xv, mut yv) = (0i64, 0, 0, 0, 0, 0, 0);
+        for (raw, native) in points {
+            let dx = raw.x as i64 * n - sx;
+            let dy = raw.y as i64 * n - sy;
+            let du = native.x as i64 * n - su;
+            let dv = native.y as i64 * n - sv;
+            xx += dx * dx;
+            yy += dy * dy;
+            xy += dx * dy;
+            xu += dx * du;
+            yu += dy * du;
+            xv += dx * dv;
+            yv += dy * dv;
+        }
+
+        let det = xx as i128 * yy as i128 - xy as i128 * xy as i128;
+        if det == 0 {
+            return Err(CalibrationError::Degenerate);
+        }
+        // nearly on a line the coefficients blow up
+        let coefficient = |p: i64, q: i64, r: i64, s: i64| {
+            let k = ((p as i128 * q as i128 - r as i128 * s as i128) << SHIFT) / det;
+            i32::try_from(k).map_err(|_| CalibrationError::Degenerate)
+        };
+
+        let a = coefficient(yy, xu, xy, yu)?;
+        let b = coefficient(xx, yu, xy, xu)?;
+        let d = coefficient(yy, xv, xy, yv)?;
+        let e = coefficient(xx, yv, xy, xv)?;
+
+        // through the means
+        let offset = |sum: i64, p: i32, q: i32| {
+            let k = ((sum << SHIFT) - p as i64 * sx - q as i64 * sy) / n;
+            i32::try_from(k).map_err(|_| CalibrationError::Degenerate)
+        };
+        let c = offset(su, a, b)?;
+        let f = offset(sv, d, e)?;
+
+        let calibration = Calibration { a, b, c, d, e, f };
+        let error = calibration.error(points);
+        if points.len() > MIN_POINTS && error > MAX_ERROR {
+            return Err(CalibrationError::Inaccurate(error));
+        }
+        Ok(calibration)
+    }
+
+    /// Largest distance in pixels (rounded up) between native points
+    /// and where their raw readings map to
+    pub fn error(&self, points: &[(Point, Point)]) -> u32 {
+        points
+            .iter()
+            .map(|(raw, native)| {
+                let d = self.native(*raw) - *native;
+                let (dx, dy) = (d.x as i64, d.y as i64);
+                let d2 = (dx * dx + dy * dy) as u64;
+                let r = crate::math::isqrt(d2);
+                (if r * r < d2 { r + 1 } else { r }) as u32
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Raw reading in LCD native coordinates, unclamped
+    pub fn native(&self, raw: Point) -> Point {
+        let (x, y) = (raw.x as i64, raw.y as i64);
+        let map = |p: i32, q: i32, r: i32| {
+            ((p as i64 * x + q as i64 * y + r as i64 + (1 << (SHIFT - 1))) >> SHIFT) as i32
+        };
+        Point::new(map(self.a, self.b, self.c), map(self.d, self.e, self.f))
+    }
+
+    /// Sample in `rotation`'s screen coordinates, clamped to the display
+    pub fn map(&self, sample: &Sample, rotation: Rotation) -> Point {
+        let size = Rotation::native_size();
+        let p = self.native(Point::new(sample.x as i32, sample.y as i32));
+        let clamped = Point::new(
+            p.x.max(0).min(size.width as i32 - 1),
+            p.y.max(0).min(size.height as i32 - 1),
+        );
+        rotation.screen_point(clamped)
+    }
+
+    pub fn to_bytes(&self) -> [u8; SIZE] {
+        let mut bytes = [0u8; SIZE];
+        bytes[0..2].copy_from_slice(MAGIC);
+        bytes[2] = VERSION;
+        let coefficients = [self.a, self.b, self.c, self.d, self.e, self.f];
+        for (chunk, k) in bytes[4..].chunks_exact_mut(4).zip(coefficients.iter()) {
+            chunk.copy_from_slice(&k.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CalibrationError> {
+        if bytes.len() < 4 {
+            return Err(CalibrationError::Truncated);
+        }
+        if &bytes[0..2] != MAGIC {
+            return Err(CalibrationError::BadMagic);
+        }
+        if bytes[2] != VERSION {
+            return Err(CalibrationError::Unsupported);
+        }
+        if bytes.len() < SIZE {
+            return Err(CalibrationError::Truncated);
+        }
+
+        let k = |i: usize| {
+            let at = 4 + i * 4;
+            i32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        Ok(Calibration {
+            a: k(0),
+            b: k(1),
+            c: k(2),
+            d: k(3),
+            e: k(4),
+            f: k(5),
+        })
+    }
+}
+
+/// Calibration run: shows one crosshair at a time, averages the readings
+/// while the pen is on it, moves on when the pen lifts.
+/// Touch events come in through `handle_touch`, drawing happens in `render`,
+/// so it works both from a polling loop and from interrupt driven input.
+pub struct Calibrator {
+    rotation: Rotation,
+    /// Native coordinates
+    targets: [Point; MAX_POINTS],
+    count: usize,
+    raw: [Point; MAX_POINTS],
+    current: usize,
+    /// Raw readings of the current target
+    sum: (u32, u32),
+    samples: u32,
+    /// Target on screen, in screen coordinates
+    shown: Option<Point>,
+}
+
+impl Calibrator {
+    /// `points` targets, MIN_POINTS..=MAX_POINTS, drawn for the display in `rotation`
+    pub fn new(points: usize, rotation: Rotation) -> Result<Self, CalibrationError> {
+        if !(MIN_POINTS..=MAX_POINTS).contains(&points) {
+            return Err(CalibrationError::PointCount);
+        }
+
+        // an eighth in from the edges, away from the corners for 3 points
+        let size = Rotation::native_size();
+        let (w, h) = (size.width as i32, size.height as i32);
+        let (l, r, t, b) = (w / 8, w - 1 - w / 8, h / 8, h - 1 - h / 8);
+        let center = Point::new(w / 2, h / 2);
+        let mut targets = [center; MAX_POINTS];
+        let layout: &[Point] = match points {
+            3 => &[Point::new(l, t), Point::new(r, h / 2), Point::new(w / 2, b)],
+            _ => &[
+                Point::new(l, t),
+                Point::new(r, t),
+                Point::new(r, b),
+                Point::new(l, b),
+                center,
+            ],
+        };
+        targets[..points].copy_from_slice(&layout[..points]);
+
+        Ok(Calibrator {
+            rotation,
+            targets,
+            count: points,
+            raw: [Point::zero(); MAX_POINTS],
+            current: 0,
+            sum: (0, 0),
+            samples: 0,
+            shown: None,
+        })
+    }
+
+    /// Starts over from the first target, e.g. after `Inaccurate`
+    pub fn restart(&mut self) {
+        self.current = 0;
+        self.sum = (0, 0);
+        self.samples = 0;
+    }
+
+    /// Targets touched so far
+    pub fn progress(&self) -> usize {
+        self.current
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.current == self.count
+    }
+
+    /// Takes the pen, gives the result once the last target is released
+    pub fn handle_touch(&mut self, touch: Touch) -> Option<Result<Calibration, CalibrationError>> {
+        if self.is_done() {
+            return None;
+        }
+
+        match touch {
+            Touch::Down(s) | Touch::Move(s) => {
+                self.sum.0 += s.x as u32;
+                self.sum.1 += s.y as u32;
+                self.samples += 1;
+                None
+            }
+            Touch::Up if self.samples > 0 => {
+                self.raw[self.current] = Point::new(
+                    (self.sum.0 / self.samples) as i32,
+                    (self.sum.1 / self.samples) as i32,
+                );
+                self.sum = (0, 0);
+                self.samples = 0;
+                self.current += 1;
+
+                if !self.is_done() {
+                    return None;
+                }
+                let mut points = [(Point::zero(), Point::zero()); MAX_POINTS];
+                for (i, p) in points[..self.count].iter_mut().enumerate() {
+                    *p = (self.raw[i], self.targets[i]);
+                }
+                Some(Calibration::solve(&points[..self.count]))
+            }
+            Touch::Up => None,
+        }
+    }
+
+    /// Moves the crosshair to the current target, erasing it once done
+    pub fn render<T>(
+        &mut self,
+        target: &mut T,
+        color: Rgb565,
+        background: Rgb565,
+    ) -> Result<(), T::Error>
+    where
+        T: DrawTarget<Rgb565>,
+    {
+        let next = if self.is_done() {
+            None
+        } else {
+            Some(self.rotation.screen_point(self.targets[self.current]))
+        };
+        if next == self.shown {
+            return Ok(());
+        }
+
+        if let Some(p) = self.shown {
+            crosshair(target, p, background)?;
+        }
+        if let Some(p) = next {
+            crosshair(target, p, color)?;
+        }
+        self.shown = next;
+        Ok(())
+    }
+}
+
+fn crosshair<T>(target: &mut T, p: Point, color: Rgb565) -> Result<(), T::Error>
+where
+    T: DrawTarget<Rgb565>,
+{
+    let style = PrimitiveStyle::with_stroke(color, 1);
+    Line::new(p - Point::new(ARM, 0), p + Point::new(ARM, 0))
+        .into_styled(style)
+        .draw(target)?;
+    Line::new(p - Point::new(0, ARM), p + Point::new(0, ARM))
+        .into_styled(style)
+        .draw(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// u = x/16 + y/64 - 10, v = -x/32 + y/8 + 5, exact in 16.16
+    const EXACT: Calibration = Calibration {
+        a: 1 << 12,
+        b: 1 << 10,
+        c: -10 << 16,
+        d: -(1 << 11),
+        e: 1 << 13,
+        f: 5 << 16,
+    };
+
+    /// Raw readings, multiples of 64 so the native points are whole pixels
+    const RAW: [Point; MAX_POINTS] = [
+        Point::new(512, 640),
+        Point::new(3520, 704),
+        Point::new(3456, 3392),
+        Point::new(576, 3328),
+        Point::new(2048, 1984),
+    ];
+
+    fn points(n: usize) -> [(Point, Point); MAX_POINTS] {
+        let mut points = [(Point::zero(), Point::zero()); MAX_POINTS];
+        for (p, raw) in points.iter_mut().zip(RAW.iter()).take(n) {
+            *p = (*raw, EXACT.native(*raw));
+        }
+        points
+    }
+
+    #[test]
+    fn solves_exact_mapping() {
+        for &n in &[3, 5] {
+            let points = points(n);
+            let calibration = Calibration::solve(&points[..n]).unwrap();
+            assert_eq!(calibration, EXACT, "{} points", n);
+            assert_eq!(calibration.error(&points[..n]), 0);
+        }
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let calibration = Calibration::solve(&points(5)).unwrap();
+        let bytes = calibration.to_bytes();
+        assert_eq!(&bytes[0..2], MAGIC);
+        assert_eq!(Calibration::from_bytes(&bytes), Ok(calibration));
+
+        assert_eq!(
+            Calibration::from_bytes(&bytes[..SIZE - 1]),
+            Err(CalibrationError::Truncated)
+        );
+        let mut bad = bytes;
+        bad[0] = b'X';
+        assert_eq!(
+            Calibration::from_bytes(&bad),
+            Err(CalibrationError::BadMagic)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let points = points(5);
+        assert_eq!(
+            Calibration::solve(&points[..2]),
+            Err(CalibrationError::PointCount)
+        );
+        // all on one line
+        let line = [
+            (Point::new(100, 100), Point::new(10, 10)),
+            (Point::new(200, 200), Point::new(20, 20)),
+            (Point::new(300, 300), Point::new(30, 30)),
+        ];
+        assert_eq!(Calibration::solve(&line), Err(CalibrationError::Degenerate));
+    }
+}
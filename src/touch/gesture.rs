@@ -0,0 +1,270 @@
+//
+// Gestures out of pen samples
+//
+// The sampler feeds pen positions (screen coordinates, None once lifted) with
+// their time in CPU cycles, `Gesture`s come out: press, drag and release for
+// the widgets, plus taps, double taps, long presses and swipes.
+//
+// A tap is reported as soon as the pen lifts, a double tap follows the second
+// one instead of holding the first back to see if another comes.
+//
+use embedded_graphics::geometry::Point;
+
+use crate::ui::Event;
+
+/// Gestures waiting in a `GestureQueue`
+pub const QUEUE_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Swipe {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    /// Pen went down
+    Press(Point),
+    /// Pen moved past the slop, then every new position
+    Drag(Point),
+    /// Pen lifted, where it was last
+    Release(Point),
+    Tap(Point),
+    /// Second tap near the first one, comes right after its `Tap`
+    DoubleTap(Point),
+    /// Held still, reported once while the pen is still down
+    LongPress(Point),
+    /// Quick drag, comes after the `Release`
+    Swipe(Swipe),
+}
+
+impl Gesture {
+    /// Press, drag and release as widget input, None for the rest
+    pub fn ui_event(&self) -> Option<Event> {
+        match *self {
+            Gesture::Press(p) => Some(Event::Down(p)),
+            Gesture::Drag(p) => Some(Event::Move(p)),
+            Gesture::Release(p) => Some(Event::Up(p)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GestureConfig {
+    /// Pixels the pen may wander and still tap or long press
+    pub slop: u32,
+    /// Cycles held still for a long press
+    pub long_press: u32,
+    /// Most cycles from a tap's release to the next press for a double tap
+    pub double_tap: u32,
+    /// Most cycles down for a swipe
+    pub swipe_time: u32,
+    /// Fewest pixels from press to release for a swipe
+    pub swipe_distance: u32,
+}
+
+impl GestureConfig {
+    /// Default timings for a CPU running at `sys_freq`
+    pub fn new(sys_freq: u32) -> Self {
+        let ms = sys_freq / 1000;
+        GestureConfig {
+            slop: 10,
+            long_press: 600 * ms,
+            double_tap: 300 * ms,
+            swipe_time: 300 * ms,
+            swipe_distance: 40,
+        }
+    }
+}
+
+/// Pen down since
+#[derive(Debug, Clone, Copy)]
+struct Stroke {
+    start: Point,
+    at: u32,
+    last: Point,
+    dragging: bool,
+    long_pressed: bool,
+}
+
+pub struct Gestures {
+    config: GestureConfig,
+    stroke: Option<Stroke>,
+    /// Where and when the last tap was released, for double taps
+    last_tap: Option<(Point, u32)>,
+}
+
+impl Gestures {
+    pub fn new(config: GestureConfig) -> Self {
+        Gestures {
+            config,
+            stroke: None,
+            last_tap: None,
+        }
+    }
+
+    pub fn config(&self) -> &GestureConfig {
+        &self.config
+    }
+
+    /// Pen position, None once lifted, at cycle `now` (wrapping).
+    /// Has to keep coming while the pen is down for long presses.
+    pub fn pen<F>(&mut self, pen: Option<Point>, now: u32, mut emit: F)
+    where
+        F: FnMut(Gesture),
+    {
+        let config = self.config;
+
+        match (pen, self.stroke.as_mut()) {
+            (Some(p), None) => {
+                self.stroke = Some(Stroke {
+                    start: p,
+                    at: now,
+                    last: p,
+                    dragging: false,
+                    long_pressed: false,
+                });
+                emit(Gesture::Press(p));
+            }
+
+            (Some(p), Some(stroke)) => {
+                if !stroke.dragging && !stroke.long_pressed && further(stroke.start, p, config.slop)
+                {
+                    stroke.dragging = true;
+                }
+                if stroke.dragging && p != stroke.last {
+                    emit(Gesture::Drag(p));
+                }
+                stroke.last = p;
+
+                if !stroke.dragging
+                    && !stroke.long_pressed
+                    && now.wrapping_sub(stroke.at) >= config.long_press
+                {
+                    stroke.long_pressed = true;
+                    emit(Gesture::LongPress(stroke.start));
+                }
+            }
+
+            (None, Some(stroke)) => {
+                let stroke = *stroke;
+                self.stroke = None;
+                emit(Gesture::Release(stroke.last));
+
+                if stroke.long_pressed {
+                    // already reported
+                } else if stroke.dragging {
+                    let quick = now.wrapping_sub(stroke.at) <= config.swipe_time;
+                    if quick
+                        && further(
+                            stroke.start,
+                            stroke.last,
+                            config.swipe_distance.saturating_sub(1),
+                        )
+                    {
+                        emit(Gesture::Swipe(direction(stroke.start, stroke.last)));
+                    }
+                } else {
+                    let double = self.last_tap.is_some_and(|(p, at)| {
+                        stroke.at.wrapping_sub(at) <= config.double_tap
+                            && !further(p, stroke.last, config.slop)
+                    });
+                    emit(Gesture::Tap(stroke.last));
+                    if double {
+                        emit(Gesture::DoubleTap(stroke.last));
+                        self.last_tap = None;
+                    } else {
+                        self.last_tap = Some((stroke.last, now));
+                    }
+                }
+            }
+
+            (None, None) => {}
+        }
+    }
+}
+
+/// More than `distance` pixels apart
+fn further(a: Point, b: Point, distance: u32) -> bool {
+    let d = b - a;
+    let (dx, dy) = (d.x as i64, d.y as i64);
+    dx * dx + dy * dy > distance as i64 * distance as i64
+}
+
+/// Along the longer axis
+fn direction(from: Point, to: Point) -> Swipe {
+    let d = to - from;
+    if d.x.abs() >= d.y.abs() {
+        if d.x < 0 {
+            Swipe::Left
+        } else {
+            Swipe::Right
+        }
+    } else if d.y < 0 {
+        Swipe::Up
+    } else {
+        Swipe::Down
+    }
+}
+
+/// Bounded FIFO from the sampling task to the UI, a shared RTIC resource
+pub struct GestureQueue {
+    items: [Gesture; QUEUE_LEN],
+    head: usize,
+    len: usize,
+    dropped: u32,
+}
+
+impl GestureQueue {
+    pub fn new() -> Self {
+        GestureQueue {
+            items: [Gesture::Release(Point::zero()); QUEUE_LEN],
+            head: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    /// False, and the gesture dropped, when full
+    pub fn push(&mut self, gesture: Gesture) -> bool {
+        if self.len == QUEUE_LEN {
+            self.dropped = self.dropped.wrapping_add(1);
+            return false;
+        }
+        self.items[(self.head + self.len) % QUEUE_LEN] = gesture;
+        self.len += 1;
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<Gesture> {
+        if self.len == 0 {
+            return None;
+        }
+        let gesture = self.items[self.head];
+        self.head = (self.head + 1) % QUEUE_LEN;
+        self.len -= 1;
+        Some(gesture)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gestures lost to a full queue so far
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+}
+
+impl Default for GestureQueue {
+    fn default() -> Self {
+        GestureQueue::new()
+    }
+}
@@ -0,0 +1,10 @@
+//
+// Resistive touch input
+//
+pub mod calibration;
+pub mod gesture;
+pub mod xpt2046;
+
+pub use calibration::{Calibration, CalibrationError, Calibrator};
+pub use gesture::{Gesture, GestureConfig, GestureQueue, Gestures, Swipe};
+pub use xpt2046::{Config, Filter, Sample, Touch, TouchError, Xpt2046};
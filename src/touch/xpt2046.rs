@@ -0,0 +1,249 @@
+//
+// XPT2046 (ADS7843 compatible) resistive touch controller on SPI
+//
+// https://grobotronics.com/images/datasheets/xpt2046-datasheet.pdf
+//
+// Every conversion is a control byte out, then 16 clocks with the 12 bit
+// result MSB first, starting one bit late.
+//
+use embedded_hal::{blocking::spi::Transfer, digital::v2::OutputPin};
+
+/// Control byte: start bit, 12 bit mode, differential reference
+const START: u8 = 0x80;
+/// Channels, A2..A0
+const CH_X: u8 = 0b101 << 4;
+const CH_Y: u8 = 0b001 << 4;
+const CH_Z1: u8 = 0b011 << 4;
+const CH_Z2: u8 = 0b100 << 4;
+/// Power down between conversions, PENIRQ enabled
+const PD_IRQ: u8 = 0b00;
+/// ADC stays on, PENIRQ disabled, for back to back conversions
+const PD_ADC_ON: u8 = 0b01;
+
+/// Most samples averaged or filtered per coordinate
+pub const MAX_SAMPLES: usize = 16;
+
+/// Largest reading, 12 bits
+pub const MAX_VALUE: u16 = 4095;
+
+#[derive(Debug, Clone, Copy)]
+pub enum TouchError<E> {
+    Spi(E),
+    ChipSelect,
+}
+
+/// How the samples of one coordinate become one value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Mean of all samples
+    Average,
+    /// Middle sample, ignores outliers
+    Median,
+    /// Mean of the middle half, outliers dropped and noise averaged
+    MedianAverage,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Samples per coordinate, 1..=MAX_SAMPLES
+    pub samples: u8,
+    pub filter: Filter,
+    /// Pressure the pen has to reach to count as down
+    pub threshold: u16,
+    /// Readings whose filtered samples spread wider than this are dropped
+    /// as unsettled (pen landing or lifting), 0 keeps everything
+    pub max_spread: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            samples: 7,
+            filter: Filter::MedianAverage,
+            threshold: 300,
+            max_spread: 40,
+        }
+    }
+}
+
+/// One filtered reading, raw ADC values
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Sample {
+    pub x: u16,
+    pub y: u16,
+    pub z1: u16,
+    pub z2: u16,
+}
+
+impl Sample {
+    /// Grows with how hard the pen presses, 0 when not touched
+    pub fn pressure(&self) -> u16 {
+        if self.z1 == 0 {
+            return 0;
+        }
+        (self.z1 + MAX_VALUE).saturating_sub(self.z2)
+    }
+
+    /// Touch resistance in ohms given the X plate resistance,
+    /// drops as the pen presses harder, None when not touched
+    pub fn resistance(&self, x_plate_ohms: u32) -> Option<u32> {
+        if self.z1 == 0 || self.z2 <= self.z1 {
+            return None;
+        }
+        let r = x_plate_ohms as u64 * self.x as u64 * (self.z2 - self.z1) as u64
+            / (4096 * self.z1 as u64);
+        Some(r as u32)
+    }
+}
+
+/// Pen state changes and movement
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Touch {
+    Down(Sample),
+    /// Still down, new position
+    Move(Sample),
+    Up,
+}
+
+pub struct Xpt2046<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+    config: Config,
+    down: bool,
+}
+
+impl<SPI, CS, E> Xpt2046<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E>,
+    CS: OutputPin,
+{
+    /// SPI mode 0, at most 2MHz
+    pub fn new(spi: SPI, cs: CS, config: Config) -> Self {
+        Xpt2046 {
+            spi,
+            cs,
+            config,
+            down: false,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Pen state as of the last `poll`
+    pub fn is_down(&self) -> bool {
+        self.down
+    }
+
+    /// Gives back the bus and pin
+    pub fn release(self) -> (SPI, CS) {
+        (self.spi, self.cs)
+    }
+
+    /// Reads the panel, tells what changed since the last call.
+    /// Unsettled readings (pen landing or lifting) change nothing.
+    pub fn poll(&mut self) -> Result<Option<Touch>, TouchError<E>> {
+        let sample = self.read()?;
+        let pressed = sample.is_some_and(|s| s.pressure() >= self.config.threshold);
+
+        Ok(match (self.down, pressed, sample) {
+            (false, true, Some(s)) => {
+                self.down = true;
+                Some(Touch::Down(s))
+            }
+            (true, true, Some(s)) => Some(Touch::Move(s)),
+            (true, false, Some(_)) => {
+                self.down = false;
+                Some(Touch::Up)
+            }
+            _ => None,
+        })
+    }
+
+    /// One filtered reading, None if the position samples were too far apart.
+    /// Position is only read (and left 0) when pressed past the threshold.
+    pub fn read(&mut self) -> Result<Option<Sample>, TouchError<E>> {
+        self.cs.set_low().map_err(|_| TouchError::ChipSelect)?;
+        let res = self.read_selected();
+        self.cs.set_high().map_err(|_| TouchError::ChipSelect)?;
+        res
+    }
+
+    fn read_selected(&mut self) -> Result<Option<Sample>, TouchError<E>> {
+        // pressure first, it's what tells if the rest means anything
+        let mut sample = Sample {
+            z1: self.channel(CH_Z1, 0)?.unwrap_or(0),
+            z2: self.channel(CH_Z2, 0)?.unwrap_or(0),
+            ..Sample::default()
+        };
+
+        let mut settled = true;
+        if sample.pressure() >= self.config.threshold {
+            let spread = self.config.max_spread;
+            match (self.channel(CH_X, spread)?, self.channel(CH_Y, spread)?) {
+                (Some(x), Some(y)) => {
+                    sample.x = x;
+                    sample.y = y;
+                }
+                _ => settled = false,
+            }
+        }
+
+        // back to power down with PENIRQ on
+        self.convert(START | CH_Y | PD_IRQ)?;
+
+        Ok(if settled { Some(sample) } else { None })
+    }
+
+    /// Filtered value of a channel, the first conversion after switching is dropped
+    fn channel(&mut self, channel: u8, max_spread: u16) -> Result<Option<u16>, TouchError<E>> {
+        let cmd = START | channel | PD_ADC_ON;
+        let n = (self.config.samples as usize).clamp(1, MAX_SAMPLES);
+
+        self.convert(cmd)?;
+        let mut samples = [0u16; MAX_SAMPLES];
+        for s in samples[..n].iter_mut() {
+            *s = self.convert(cmd)?;
+        }
+
+        Ok(filter(&mut samples[..n], self.config.filter, max_spread))
+    }
+
+    fn convert(&mut self, cmd: u8) -> Result<u16, TouchError<E>> {
+        let mut buf = [cmd, 0, 0];
+        self.spi.transfer(&mut buf).map_err(TouchError::Spi)?;
+        Ok((((buf[1] as u16) << 8 | buf[2] as u16) >> 3) & MAX_VALUE)
+    }
+}
+
+/// Sorts the samples and reduces them to one value
+fn filter(samples: &mut [u16], filter: Filter, max_spread: u16) -> Option<u16> {
+    // few samples, insertion sort
+    for i in 1..samples.len() {
+        let mut j = i;
+        while j > 0 && samples[j - 1] > samples[j] {
+            samples.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+
+    let n = samples.len();
+    let used = match filter {
+        Filter::Average => &samples[..],
+        Filter::Median => &samples[n / 2..n / 2 + 1],
+        Filter::MedianAverage => &samples[n / 4..n - n / 4],
+    };
+
+    let spread = used[used.len() - 1] - used[0];
+    if max_spread > 0 && spread > max_spread {
+        return None;
+    }
+
+    let sum: u32 = used.iter().map(|s| *s as u32).sum();
+    Some((sum / used.len() as u32) as u16)
+}
@@ -1,3 +1,66 @@
-use stm32f1xx_hal::gpio::*;
+use stm32f1xx_hal::{
+    gpio::*,
+    i2c::BlockingI2c,
+    pac::{I2C1, SPI1, SPI3, TIM2, USART1},
+    pwm::{Pwm, C3},
+    serial::Serial,
+    spi::{Spi, Spi1NoRemap, Spi3Remap},
+    timer::Tim2NoRemap,
+};
 
-pub type BeeperPin = gpioa::PA2<Output<PushPull>>;
+/// TIM2 CH3
+pub type BeeperPin = gpioa::PA2<Alternate<PushPull>>;
+pub type BeeperPwm = Pwm<TIM2, Tim2NoRemap, C3, BeeperPin>;
+
+/// SPI1 on PA5 SCK / PA6 MISO / PA7 MOSI, touch controller (and SPI flash)
+pub type Spi1 = Spi<
+    SPI1,
+    Spi1NoRemap,
+    (
+        gpioa::PA5<Alternate<PushPull>>,
+        gpioa::PA6<Input<Floating>>,
+        gpioa::PA7<Alternate<PushPull>>,
+    ),
+    u8,
+>;
+
+/// XPT2046 chip select
+pub type TouchCsPin = gpioc::PC9<Output<PushPull>>;
+/// XPT2046 PENIRQ, low while touched
+pub type TouchIrqPin = gpioc::PC5<Input<PullUp>>;
+
+/// SPI flash chip select, shares SPI1 with the touch controller
+pub type FlashCsPin = gpiob::PB9<Output<PushPull>>;
+
+/// SPI3 remapped to PC10 SCK / PC11 MISO / PC12 MOSI, SD card slot
+pub type Spi3 = Spi<
+    SPI3,
+    Spi3Remap,
+    (
+        gpioc::PC10<Alternate<PushPull>>,
+        gpioc::PC11<Input<Floating>>,
+        gpioc::PC12<Alternate<PushPull>>,
+    ),
+    u8,
+>;
+
+/// SD card chip select
+pub type SdCsPin = gpiod::PD11<Output<PushPull>>;
+
+/// USART1 on the PA9 TX / PA10 RX header
+pub type Serial1 = Serial<
+    USART1,
+    (
+        gpioa::PA9<Alternate<PushPull>>,
+        gpioa::PA10<Input<Floating>>,
+    ),
+>;
+
+/// I2C1 on PB6 SCL / PB7 SDA, AT24Cxx EEPROM
+pub type I2c1 = BlockingI2c<
+    I2C1,
+    (
+        gpiob::PB6<Alternate<OpenDrain>>,
+        gpiob::PB7<Alternate<OpenDrain>>,
+    ),
+>;
@@ -0,0 +1,136 @@
+//
+// Screen areas and stacking containers that hand them out to widgets
+//
+use embedded_graphics::{
+    geometry::{Point, Size},
+    primitives::Rectangle,
+};
+
+/// Rectangle on screen, `size` pixels from `top_left`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Area {
+    pub top_left: Point,
+    pub size: Size,
+}
+
+impl Area {
+    pub fn new(top_left: Point, size: Size) -> Self {
+        Area { top_left, size }
+    }
+
+    /// Last pixel inside, the rectangle corners embedded-graphics (and `Lcd`) expect
+    pub fn bottom_right(&self) -> Point {
+        self.top_left + Point::new(self.size.width as i32 - 1, self.size.height as i32 - 1)
+    }
+
+    pub fn rectangle(&self) -> Rectangle {
+        Rectangle::new(self.top_left, self.bottom_right())
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        let d = p - self.top_left;
+        d.x >= 0 && d.y >= 0 && (d.x as u32) < self.size.width && (d.y as u32) < self.size.height
+    }
+
+    /// Shrunk by `n` pixels on every side
+    pub fn inset(&self, n: u32) -> Self {
+        Area {
+            top_left: self.top_left + Point::new(n as i32, n as i32),
+            size: Size::new(
+                self.size.width.saturating_sub(2 * n),
+                self.size.height.saturating_sub(2 * n),
+            ),
+        }
+    }
+
+    /// Point where something of `size` sits centered
+    pub fn center(&self, size: Size) -> Point {
+        self.top_left
+            + Point::new(
+                (self.size.width as i32 - size.width as i32) / 2,
+                (self.size.height as i32 - size.height as i32) / 2,
+            )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// Top to bottom
+    Column,
+    /// Left to right
+    Row,
+}
+
+/// Cuts an area into consecutive slices along one direction,
+/// stacks nest by cutting one of the slices again.
+#[derive(Debug, Clone, Copy)]
+pub struct Stack {
+    area: Area,
+    direction: Direction,
+    spacing: u32,
+    /// Used along the direction so far
+    used: u32,
+}
+
+impl Stack {
+    pub fn new(area: Area, direction: Direction, spacing: u32) -> Self {
+        Stack {
+            area,
+            direction,
+            spacing,
+            used: 0,
+        }
+    }
+
+    pub fn column(area: Area, spacing: u32) -> Self {
+        Stack::new(area, Direction::Column, spacing)
+    }
+
+    pub fn row(area: Area, spacing: u32) -> Self {
+        Stack::new(area, Direction::Row, spacing)
+    }
+
+    /// Space left along the direction
+    pub fn remaining(&self) -> u32 {
+        self.length().saturating_sub(self.used)
+    }
+
+    fn length(&self) -> u32 {
+        match self.direction {
+            Direction::Column => self.area.size.height,
+            Direction::Row => self.area.size.width,
+        }
+    }
+
+    /// Next slice, `length` pixels along the direction (less if the stack runs out),
+    /// full size across it.
+    pub fn next(&mut self, length: u32) -> Area {
+        let offset = self.used.min(self.length());
+        let length = length.min(self.remaining());
+        self.used += length + self.spacing;
+
+        match self.direction {
+            Direction::Column => Area::new(
+                self.area.top_left + Point::new(0, offset as i32),
+                Size::new(self.area.size.width, length),
+            ),
+            Direction::Row => Area::new(
+                self.area.top_left + Point::new(offset as i32, 0),
+                Size::new(length, self.area.size.height),
+            ),
+        }
+    }
+
+    /// Whatever is left
+    pub fn rest(&mut self) -> Area {
+        let length = self.remaining();
+        self.next(length)
+    }
+
+    /// Splits what is left into `n` equal slices, `n` must be at least 1
+    pub fn split(&mut self, n: u32) -> impl Iterator<Item = Area> + '_ {
+        let n = n.max(1);
+        let length = self.remaining().saturating_sub(self.spacing * (n - 1)) / n;
+        (0..n).map(move |_| self.next(length))
+    }
+}
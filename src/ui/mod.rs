@@ -0,0 +1,443 @@
+//
+// Retained mode widgets: buttons, labels, sliders, progress bars and lists.
+//
+// Widgets live in storage the caller provides (no heap), get their areas from
+// `layout::Stack` containers and only redraw when something about them changed.
+// Input comes in as `Event`s, from touch or from keys moving the focus around.
+//
+//   let mut storage: [Option<Widget>; 8] = Default::default();
+//   let mut ui = Ui::new(&mut storage, Theme::dark());
+//   let mut column = Stack::column(Area::new(Point::new(10, 10), Size::new(220, 300)), 4);
+//   let ok = ui.add(Widget::button("OK"), column.next(24))?;
+//   ...
+//   if let Some(Response { id, action: Action::Clicked }) = ui.handle_event(event) { ... }
+//   ui.render(lcd)?;
+//
+pub mod layout;
+pub mod screen;
+pub mod widget;
+
+use core::cell::Cell;
+
+use embedded_graphics::{geometry::Point, pixelcolor::Rgb565, prelude::*, DrawTarget};
+
+pub use layout::{Area, Direction, Stack};
+pub use screen::{Navigator, Screen, ScreenId, Transition};
+pub use widget::{Kind, Widget};
+
+/// Input driving the widgets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// Pen touched the screen, screen coordinates
+    Down(Point),
+    /// Pen moved while down
+    Move(Point),
+    /// Pen lifted
+    Up(Point),
+    FocusNext,
+    FocusPrevious,
+    /// Slider up, list down
+    Increment,
+    Decrement,
+    /// Clicks the focused button or picks the focused list item
+    Activate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Clicked,
+    /// Slider moved to the value
+    Changed(i32),
+    /// List item picked
+    Selected(usize),
+}
+
+/// What an event did to which widget
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Response {
+    pub id: WidgetId,
+    pub action: Action,
+}
+
+/// Handle of a widget added to a `Ui`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WidgetId(usize);
+
+#[derive(Debug, Clone, Copy)]
+pub enum UiError {
+    /// No room left in the widget storage
+    Full,
+}
+
+/// Widget colors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub background: Rgb565,
+    pub foreground: Rgb565,
+    /// Button faces, list and progress bar backgrounds
+    pub surface: Rgb565,
+    /// Pressed buttons, filled part of sliders and progress bars, list selection
+    pub accent: Rgb565,
+    pub border: Rgb565,
+    /// Border of the focused widget
+    pub focus: Rgb565,
+    /// Text of disabled widgets
+    pub disabled: Rgb565,
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Theme {
+            background: Rgb565::BLACK,
+            foreground: Rgb565::WHITE,
+            surface: Rgb565::new(4, 8, 4),
+            accent: Rgb565::new(0, 40, 31),
+            border: Rgb565::new(12, 24, 12),
+            focus: Rgb565::YELLOW,
+            disabled: Rgb565::new(12, 24, 12),
+        }
+    }
+
+    pub fn light() -> Self {
+        Theme {
+            background: Rgb565::WHITE,
+            foreground: Rgb565::BLACK,
+            surface: Rgb565::new(28, 56, 28),
+            accent: Rgb565::new(0, 20, 20),
+            border: Rgb565::new(16, 32, 16),
+            focus: Rgb565::new(31, 32, 0),
+            disabled: Rgb565::new(20, 40, 20),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::dark()
+    }
+}
+
+/// Counts presses buttons, sliders and lists took, e.g. to click for them.
+/// One can be shared by the `Ui`s of all screens.
+#[derive(Debug, Default)]
+pub struct Feedback {
+    presses: Cell<u32>,
+}
+
+impl Feedback {
+    pub fn new() -> Self {
+        Feedback::default()
+    }
+
+    /// Presses since the last call
+    pub fn take(&self) -> u32 {
+        self.presses.replace(0)
+    }
+
+    fn press(&self) {
+        self.presses.set(self.presses.get().wrapping_add(1));
+    }
+}
+
+pub struct Ui<'a, 'w> {
+    widgets: &'w mut [Option<Widget<'a>>],
+    feedback: Option<&'w Feedback>,
+    theme: Theme,
+    focus: Option<WidgetId>,
+    /// Widget the pen went down on, gets the moves and the release
+    captured: Option<WidgetId>,
+}
+
+impl<'a, 'w> Ui<'a, 'w> {
+    /// `storage` bounds the number of widgets, it's emptied first
+    pub fn new(storage: &'w mut [Option<Widget<'a>>], theme: Theme) -> Self {
+        for w in storage.iter_mut() {
+            *w = None;
+        }
+
+        Ui {
+            widgets: storage,
+            feedback: None,
+            theme,
+            focus: None,
+            captured: None,
+        }
+    }
+
+    /// Places a widget, it gets drawn on the next `render`
+    pub fn add(&mut self, mut widget: Widget<'a>, area: Area) -> Result<WidgetId, UiError> {
+        let i = self
+            .widgets
+            .iter()
+            .position(|w| w.is_none())
+            .ok_or(UiError::Full)?;
+
+        widget.area = area;
+        widget.dirty = true;
+        // keeps a list's selection in view of its area
+        let value = widget.value();
+        widget.set_value(value);
+
+        self.widgets[i] = Some(widget);
+        Ok(WidgetId(i))
+    }
+
+    /// Drops a widget, whatever it drew stays on screen
+    pub fn remove(&mut self, id: WidgetId) {
+        if self.focus == Some(id) {
+            self.focus = None;
+        }
+        if self.captured == Some(id) {
+            self.captured = None;
+        }
+        if let Some(w) = self.widgets.get_mut(id.0) {
+            *w = None;
+        }
+    }
+
+    pub fn widget(&self, id: WidgetId) -> Option<&Widget<'a>> {
+        self.widgets.get(id.0).and_then(|w| w.as_ref())
+    }
+
+    fn widget_mut(&mut self, id: WidgetId) -> Option<&mut Widget<'a>> {
+        self.widgets.get_mut(id.0).and_then(|w| w.as_mut())
+    }
+
+    /// Slider value, progress percent or selected list item, 0 otherwise
+    pub fn value(&self, id: WidgetId) -> i32 {
+        self.widget(id).map_or(0, |w| w.value())
+    }
+
+    /// Sets a slider value, progress percent or list selection, clamped
+    pub fn set_value(&mut self, id: WidgetId, value: i32) {
+        if let Some(w) = self.widget_mut(id) {
+            w.set_value(value);
+        }
+    }
+
+    /// Changes label or button text
+    pub fn set_text(&mut self, id: WidgetId, text: &'a str) {
+        if let Some(w) = self.widget_mut(id) {
+            w.set_text(text);
+        }
+    }
+
+    /// Disabled widgets are drawn grayed out and ignore input
+    pub fn set_enabled(&mut self, id: WidgetId, enabled: bool) {
+        if !enabled && self.focus == Some(id) {
+            self.set_focus(None);
+        }
+        if let Some(w) = self.widget_mut(id) {
+            if w.enabled != enabled {
+                w.enabled = enabled;
+                w.dirty = true;
+            }
+        }
+    }
+
+    pub fn focus(&self) -> Option<WidgetId> {
+        self.focus
+    }
+
+    pub fn set_focus(&mut self, id: Option<WidgetId>) {
+        if self.focus == id {
+            return;
+        }
+        if let Some(old) = self.focus {
+            self.invalidate(old);
+        }
+        if let Some(new) = id {
+            self.invalidate(new);
+        }
+        self.focus = id;
+    }
+
+    /// Where presses on enabled widgets are counted
+    pub fn set_feedback(&mut self, feedback: &'w Feedback) {
+        self.feedback = Some(feedback);
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    /// New colors, everything redraws
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        self.invalidate_all();
+    }
+
+    pub fn invalidate(&mut self, id: WidgetId) {
+        if let Some(w) = self.widget_mut(id) {
+            w.dirty = true;
+        }
+    }
+
+    /// Redraw everything, e.g. after something else drew over the widgets
+    pub fn invalidate_all(&mut self) {
+        for w in self.widgets.iter_mut().flatten() {
+            w.dirty = true;
+        }
+    }
+
+    /// Anything waiting to be redrawn
+    pub fn is_dirty(&self) -> bool {
+        self.widgets.iter().flatten().any(|w| w.dirty)
+    }
+
+    /// Draws widgets that changed since the last render
+    pub fn render<T>(&mut self, target: &mut T) -> Result<(), T::Error>
+    where
+        T: DrawTarget<Rgb565>,
+    {
+        let focus = self.focus;
+        for (i, slot) in self.widgets.iter_mut().enumerate() {
+            if let Some(w) = slot {
+                if w.dirty {
+                    w.draw(target, &self.theme, focus == Some(WidgetId(i)))?;
+                    w.dirty = false;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Topmost (last added) enabled widget under `p`
+    fn hit(&self, p: Point) -> Option<WidgetId> {
+        self.widgets
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, w)| w.is_some_and(|w| w.enabled && w.area.contains(p)))
+            .map(|(i, _)| WidgetId(i))
+    }
+
+    /// Next focusable widget after (or before) the focused one, wrapping around
+    fn cycle_focus(&mut self, forward: bool) {
+        let n = self.widgets.len();
+        if n == 0 {
+            return;
+        }
+        let start = self.focus.map_or(if forward { n - 1 } else { 0 }, |f| f.0);
+
+        for step in 1..=n {
+            let i = if forward {
+                (start + step) % n
+            } else {
+                (start + n - step % n) % n
+            };
+            if self.widgets[i].is_some_and(|w| w.is_focusable()) {
+                self.set_focus(Some(WidgetId(i)));
+                return;
+            }
+        }
+    }
+
+    pub fn handle_event(&mut self, event: Event) -> Option<Response> {
+        match event {
+            Event::Down(p) => {
+                let id = self.hit(p)?;
+                self.captured = Some(id);
+                if self.widget(id)?.is_focusable() {
+                    self.set_focus(Some(id));
+                    if let Some(f) = self.feedback {
+                        f.press();
+                    }
+                }
+                self.pen(id, p, true)
+            }
+
+            Event::Move(p) => {
+                let id = self.captured?;
+                self.pen(id, p, false)
+            }
+
+            Event::Up(p) => {
+                let id = self.captured.take()?;
+                let w = self.widget_mut(id)?;
+                match w.kind {
+                    Kind::Button { pressed: true, .. } => {
+                        w.set_pressed(false);
+                        if w.area.contains(p) {
+                            return respond(id, Action::Clicked);
+                        }
+                        None
+                    }
+                    _ => None,
+                }
+            }
+
+            Event::FocusNext => {
+                self.cycle_focus(true);
+                None
+            }
+
+            Event::FocusPrevious => {
+                self.cycle_focus(false);
+                None
+            }
+
+            Event::Increment | Event::Decrement => {
+                let id = self.focus?;
+                let w = self.widget_mut(id)?;
+                let up = event == Event::Increment;
+                match w.kind {
+                    Kind::Slider { value, step, .. } => {
+                        let v = if up { value + step } else { value - step };
+                        if w.set_value(v) {
+                            return respond(id, Action::Changed(w.value()));
+                        }
+                        None
+                    }
+                    Kind::List { selected, .. } => {
+                        let v = if up {
+                            selected + 1
+                        } else {
+                            selected.saturating_sub(1)
+                        };
+                        w.set_value(v as i32);
+                        None
+                    }
+                    _ => None,
+                }
+            }
+
+            Event::Activate => {
+                let id = self.focus?;
+                match self.widget(id)?.kind {
+                    Kind::Button { .. } => respond(id, Action::Clicked),
+                    Kind::List { selected, .. } => respond(id, Action::Selected(selected)),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Pen down (`down`) or moving over a captured widget
+    fn pen(&mut self, id: WidgetId, p: Point, down: bool) -> Option<Response> {
+        let w = self.widget_mut(id)?;
+        match w.kind {
+            Kind::Button { .. } => {
+                w.set_pressed(w.area.contains(p));
+                None
+            }
+            Kind::Slider { .. } => {
+                let v = w.slider_value_at(p.x)?;
+                if w.set_value(v) {
+                    return respond(id, Action::Changed(v));
+                }
+                None
+            }
+            Kind::List { .. } if down => {
+                let i = w.list_item_at(p.y)?;
+                w.set_value(i as i32);
+                respond(id, Action::Selected(i))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn respond(id: WidgetId, action: Action) -> Option<Response> {
+    Some(Response { id, action })
+}
@@ -0,0 +1,316 @@
+//
+// Screens and navigation between them.
+//
+// A `Navigator` keeps a bounded back stack of screens plus an optional modal
+// dialog on top. Events go to the topmost screen, whose answer (`Transition`)
+// moves the stack. Screens changing clear the display, or slide the old one
+// away with the panel's hardware scroll, before the new one renders.
+//
+// Nothing is allocated, the screens are owned by the caller (e.g. on the `idle` stack)
+// and handed over as a slice, `ScreenId` is an index into it.
+//
+use embedded_graphics::{pixelcolor::Rgb565, DrawTarget};
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use super::Event;
+use crate::lcd::{Lcd, LcdError};
+
+/// Deepest back stack
+pub const MAX_DEPTH: usize = 8;
+
+/// GRAM lines scrolled per slide step
+const SLIDE_STEP: u16 = 16;
+
+pub trait Screen<T>
+where
+    T: DrawTarget<Rgb565>,
+{
+    /// Became the topmost screen, display is blank (or still shows what's
+    /// under a modal) so everything has to be drawn on the next `render`
+    fn enter(&mut self);
+
+    fn handle_event(&mut self, event: Event) -> Transition;
+
+    /// Draws what changed since the last call
+    fn render(&mut self, target: &mut T) -> Result<(), T::Error>;
+
+    /// Stops being the topmost screen, e.g. covered by a modal or pushed down the stack
+    fn leave(&mut self);
+}
+
+/// Index of a screen in the slice given to `Navigator::new`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    Stay,
+    /// Opens a screen on top, back goes to the current one
+    Push(ScreenId),
+    /// Swaps the current screen, keeping the stack below
+    Replace(ScreenId),
+    /// Back to the previous screen, or closes the modal
+    Pop,
+    /// Back to the bottom of the stack
+    Home,
+    /// Dialog over the current screen, which stays visible but gets no events
+    Modal(ScreenId),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum NavError {
+    StackFull,
+    NoScreen,
+}
+
+/// How the display changes on the next render, stronger ones win
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Change {
+    /// Modal goes over whatever is there
+    Overlay,
+    Clear,
+    /// Clear by sliding the old screen away, forward or back
+    Slide(bool),
+}
+
+/// Targets that can move their contents with a hardware scroll
+pub trait Scroll: DrawTarget<Rgb565> {
+    /// Lines a full scroll goes through
+    fn scroll_lines(&self) -> u16;
+
+    fn set_scroll(&mut self, lines: u16) -> Result<(), Self::Error>;
+
+    /// Fills `count` lines from `first` in unscrolled scroll coordinates
+    fn fill_lines(&mut self, first: u16, count: u16, color: Rgb565) -> Result<(), Self::Error>;
+}
+
+impl<D> Scroll for Lcd<D>
+where
+    D: DelayMs<u16> + DelayUs<u16>,
+{
+    fn scroll_lines(&self) -> u16 {
+        self.gram_lines()
+    }
+
+    fn set_scroll(&mut self, lines: u16) -> Result<(), LcdError> {
+        Lcd::set_scroll(self, lines)
+    }
+
+    fn fill_lines(&mut self, first: u16, count: u16, color: Rgb565) -> Result<(), LcdError> {
+        self.fill_gram_lines(first, count, color)
+    }
+}
+
+pub struct Navigator<'n, 's, T>
+where
+    T: DrawTarget<Rgb565>,
+{
+    screens: &'n mut [&'s mut dyn Screen<T>],
+    stack: [ScreenId; MAX_DEPTH],
+    depth: usize,
+    modal: Option<ScreenId>,
+    background: Rgb565,
+    slides: bool,
+    pending: Option<Change>,
+}
+
+impl<'n, 's, T> Navigator<'n, 's, T>
+where
+    T: DrawTarget<Rgb565>,
+{
+    /// Starts with `root` as the only screen, the display is cleared to `background`
+    /// whenever screens change
+    pub fn new(
+        screens: &'n mut [&'s mut dyn Screen<T>],
+        root: ScreenId,
+        background: Rgb565,
+    ) -> Result<Self, NavError> {
+        if root.0 >= screens.len() {
+            return Err(NavError::NoScreen);
+        }
+        screens[root.0].enter();
+
+        Ok(Navigator {
+            screens,
+            stack: [root; MAX_DEPTH],
+            depth: 1,
+            modal: None,
+            background,
+            slides: false,
+            pending: Some(Change::Clear),
+        })
+    }
+
+    /// Slide the old screen away on push and pop, needs `render_animated`
+    pub fn set_slides(&mut self, slides: bool) {
+        self.slides = slides;
+    }
+
+    /// Screen getting the events, the modal if one is open
+    pub fn current(&self) -> ScreenId {
+        self.modal.unwrap_or(self.stack[self.depth - 1])
+    }
+
+    /// Screens on the back stack, the modal not counted
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn is_modal(&self) -> bool {
+        self.modal.is_some()
+    }
+
+    /// Passes the event to the current screen and follows where it leads
+    pub fn handle_event(&mut self, event: Event) -> Result<Transition, NavError> {
+        let transition = self.screens[self.current().0].handle_event(event);
+        self.navigate(transition)?;
+        Ok(transition)
+    }
+
+    /// Moves the stack, e.g. from a timeout rather than an event
+    pub fn navigate(&mut self, transition: Transition) -> Result<(), NavError> {
+        match transition {
+            Transition::Stay => {}
+
+            Transition::Modal(id) => {
+                self.check(id)?;
+                let current = self.current();
+                self.screens[current.0].leave();
+                self.modal = Some(id);
+                self.screens[id.0].enter();
+                self.mark(Change::Overlay);
+            }
+
+            Transition::Pop if self.modal.is_some() => {
+                self.close_modal();
+                self.enter_top(Change::Clear);
+            }
+
+            Transition::Pop => {
+                if self.depth > 1 {
+                    self.leave_top();
+                    self.depth -= 1;
+                    self.enter_top(Change::Slide(false));
+                }
+            }
+
+            Transition::Push(id) => {
+                self.check(id)?;
+                if self.depth == MAX_DEPTH {
+                    return Err(NavError::StackFull);
+                }
+                self.leave_top();
+                self.stack[self.depth] = id;
+                self.depth += 1;
+                self.enter_top(Change::Slide(true));
+            }
+
+            Transition::Replace(id) => {
+                self.check(id)?;
+                self.leave_top();
+                self.stack[self.depth - 1] = id;
+                self.enter_top(Change::Slide(true));
+            }
+
+            Transition::Home => {
+                if self.depth > 1 || self.modal.is_some() {
+                    self.leave_top();
+                    self.depth = 1;
+                    self.enter_top(Change::Slide(false));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Brings the display up to date, screen changes just clear it
+    pub fn render(&mut self, target: &mut T) -> Result<(), T::Error> {
+        match self.pending.take() {
+            Some(Change::Clear) | Some(Change::Slide(_)) => target.clear(self.background)?,
+            _ => {}
+        }
+
+        let current = self.current();
+        self.screens[current.0].render(target)
+    }
+
+    fn check(&self, id: ScreenId) -> Result<(), NavError> {
+        if id.0 < self.screens.len() {
+            Ok(())
+        } else {
+            Err(NavError::NoScreen)
+        }
+    }
+
+    fn mark(&mut self, change: Change) {
+        if self.pending.is_none_or(|p| p < change) {
+            self.pending = Some(change);
+        }
+    }
+
+    fn close_modal(&mut self) {
+        if let Some(id) = self.modal.take() {
+            self.screens[id.0].leave();
+        }
+    }
+
+    /// Top of the stack, or the modal over it, stops being current
+    fn leave_top(&mut self) {
+        if self.modal.is_some() {
+            self.close_modal();
+        } else {
+            let top = self.stack[self.depth - 1];
+            self.screens[top.0].leave();
+        }
+    }
+
+    fn enter_top(&mut self, change: Change) {
+        let top = self.stack[self.depth - 1];
+        self.screens[top.0].enter();
+        self.mark(change);
+    }
+}
+
+impl<'n, 's, T> Navigator<'n, 's, T>
+where
+    T: Scroll,
+{
+    /// `render`, sliding the old screen out first when slides are on
+    pub fn render_animated(&mut self, target: &mut T) -> Result<(), T::Error> {
+        if let Some(Change::Slide(forward)) = self.pending {
+            if self.slides {
+                slide_out(target, self.background, forward)?;
+                self.pending = None;
+            }
+        }
+        self.render(target)
+    }
+}
+
+/// Scrolls the whole display away a step at a time, lines leaving one side
+/// are blanked before they come back in on the other.
+/// Leaves the display blank and unscrolled.
+fn slide_out<T>(target: &mut T, background: Rgb565, forward: bool) -> Result<(), T::Error>
+where
+    T: Scroll,
+{
+    let lines = target.scroll_lines();
+    let mut offset = 0;
+
+    while offset < lines {
+        let n = SLIDE_STEP.min(lines - offset);
+        if forward {
+            target.fill_lines(offset, n, background)?;
+            offset += n;
+            target.set_scroll(offset % lines)?;
+        } else {
+            target.fill_lines(lines - offset - n, n, background)?;
+            offset += n;
+            target.set_scroll((lines - offset) % lines)?;
+        }
+    }
+
+    target.set_scroll(0)
+}
@@ -0,0 +1,378 @@
+//
+// Widget state and drawing
+//
+use embedded_graphics::{
+    fonts::{Font6x8, Text},
+    geometry::{Point, Size},
+    pixelcolor::Rgb565,
+    prelude::*,
+    style::{PrimitiveStyle, PrimitiveStyleBuilder, TextStyle},
+    DrawTarget,
+};
+
+use super::{layout::Area, Theme};
+
+/// Font6x8 cell
+const CHAR_W: u32 = 6;
+const CHAR_H: u32 = 8;
+
+/// List row height
+pub(crate) const ROW: u32 = CHAR_H + 4;
+
+/// Slider knob width
+const KNOB: u32 = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Kind<'a> {
+    Label {
+        text: &'a str,
+    },
+    Button {
+        text: &'a str,
+        pressed: bool,
+    },
+    Slider {
+        value: i32,
+        min: i32,
+        max: i32,
+        step: i32,
+    },
+    Progress {
+        percent: u8,
+    },
+    List {
+        items: &'a [&'a str],
+        selected: usize,
+        /// First visible item
+        top: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Widget<'a> {
+    pub(crate) kind: Kind<'a>,
+    pub(crate) area: Area,
+    pub(crate) enabled: bool,
+    pub(crate) dirty: bool,
+}
+
+impl<'a> Widget<'a> {
+    fn new(kind: Kind<'a>) -> Self {
+        Widget {
+            kind,
+            area: Area::default(),
+            enabled: true,
+            dirty: true,
+        }
+    }
+
+    pub fn label(text: &'a str) -> Self {
+        Widget::new(Kind::Label { text })
+    }
+
+    pub fn button(text: &'a str) -> Self {
+        Widget::new(Kind::Button {
+            text,
+            pressed: false,
+        })
+    }
+
+    /// `min` must be below `max`, values move by `step`
+    pub fn slider(min: i32, max: i32, step: i32, value: i32) -> Self {
+        Widget::new(Kind::Slider {
+            value: value.max(min).min(max),
+            min,
+            max: max.max(min + 1),
+            step: step.max(1),
+        })
+    }
+
+    pub fn progress(percent: u8) -> Self {
+        Widget::new(Kind::Progress {
+            percent: percent.min(100),
+        })
+    }
+
+    pub fn list(items: &'a [&'a str]) -> Self {
+        Widget::new(Kind::List {
+            items,
+            selected: 0,
+            top: 0,
+        })
+    }
+
+    pub fn kind(&self) -> &Kind<'a> {
+        &self.kind
+    }
+
+    pub fn area(&self) -> Area {
+        self.area
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Labels and progress bars only show things
+    pub fn is_focusable(&self) -> bool {
+        self.enabled && !matches!(self.kind, Kind::Label { .. } | Kind::Progress { .. })
+    }
+
+    /// Slider value, progress percent or selected list item
+    pub fn value(&self) -> i32 {
+        match self.kind {
+            Kind::Slider { value, .. } => value,
+            Kind::Progress { percent } => percent as i32,
+            Kind::List { selected, .. } => selected as i32,
+            _ => 0,
+        }
+    }
+
+    /// Sets what `value` returns, clamped, true if it changed
+    pub(crate) fn set_value(&mut self, v: i32) -> bool {
+        let visible = self.visible_rows();
+        let changed = match &mut self.kind {
+            Kind::Slider {
+                value, min, max, ..
+            } => replace(value, v.max(*min).min(*max)),
+            Kind::Progress { percent } => replace(percent, v.clamp(0, 100) as u8),
+            Kind::List {
+                items,
+                selected,
+                top,
+            } => {
+                let last = items.len().saturating_sub(1) as i32;
+                let changed = replace(selected, v.max(0).min(last) as usize);
+                // keep the selection in view
+                if *selected < *top {
+                    *top = *selected;
+                } else if *selected >= *top + visible {
+                    *top = *selected + 1 - visible;
+                }
+                changed
+            }
+            _ => false,
+        };
+        self.dirty |= changed;
+        changed
+    }
+
+    pub(crate) fn set_text(&mut self, new: &'a str) {
+        match &mut self.kind {
+            Kind::Label { text } | Kind::Button { text, .. } if *text != new => {
+                *text = new;
+                self.dirty = true;
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn set_pressed(&mut self, p: bool) {
+        if let Kind::Button { pressed, .. } = &mut self.kind {
+            if replace(pressed, p) {
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Slider value under `x`, rounded to the step
+    pub(crate) fn slider_value_at(&self, x: i32) -> Option<i32> {
+        match self.kind {
+            Kind::Slider { min, max, step, .. } => {
+                let track = (self.area.size.width.saturating_sub(KNOB)).max(1) as i32;
+                let pos = (x - self.area.top_left.x - KNOB as i32 / 2)
+                    .max(0)
+                    .min(track);
+                let v = min + pos * (max - min) / track;
+                Some(min + (v - min + step / 2) / step * step)
+            }
+            _ => None,
+        }
+    }
+
+    /// List item under `y`
+    pub(crate) fn list_item_at(&self, y: i32) -> Option<usize> {
+        match self.kind {
+            Kind::List { items, top, .. } => {
+                let i = top + ((y - self.area.top_left.y - 1).max(0) as u32 / ROW) as usize;
+                if i < items.len() {
+                    Some(i)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn visible_rows(&self) -> usize {
+        (self.area.size.height.saturating_sub(2) / ROW).max(1) as usize
+    }
+
+    pub(crate) fn draw<T>(
+        &self,
+        target: &mut T,
+        theme: &Theme,
+        focused: bool,
+    ) -> Result<(), T::Error>
+    where
+        T: DrawTarget<Rgb565>,
+    {
+        let area = self.area;
+        let fg = if self.enabled {
+            theme.foreground
+        } else {
+            theme.disabled
+        };
+        let border = if focused { theme.focus } else { theme.border };
+
+        match self.kind {
+            Kind::Label { text } => {
+                fill(target, area, theme.background)?;
+                let dy = (area.size.height as i32 - CHAR_H as i32) / 2;
+                text_at(
+                    target,
+                    area.top_left + Point::new(0, dy),
+                    area.size.width,
+                    text,
+                    fg,
+                )?;
+            }
+
+            Kind::Button { text, pressed } => {
+                let face = if pressed { theme.accent } else { theme.surface };
+                boxed(target, area, face, border)?;
+                let text_color = if pressed { theme.background } else { fg };
+                centered_text(target, area.inset(2), text, text_color)?;
+            }
+
+            Kind::Slider {
+                value, min, max, ..
+            } => {
+                fill(target, area, theme.background)?;
+                let track = area.size.width.saturating_sub(KNOB);
+                let knob_x = ((value - min) as i64 * track as i64 / (max - min) as i64) as i32;
+                let mid = area.top_left.y + area.size.height as i32 / 2;
+
+                let x0 = area.top_left.x + KNOB as i32 / 2;
+                let filled = Area::new(Point::new(x0, mid - 2), Size::new(knob_x as u32, 4));
+                let empty = Area::new(
+                    Point::new(x0 + knob_x, mid - 2),
+                    Size::new(track - knob_x as u32, 4),
+                );
+                fill(target, filled, theme.accent)?;
+                fill(target, empty, theme.border)?;
+
+                let knob = Area::new(
+                    Point::new(area.top_left.x + knob_x, area.top_left.y),
+                    Size::new(KNOB, area.size.height),
+                );
+                boxed(target, knob, theme.surface, border)?;
+            }
+
+            Kind::Progress { percent } => {
+                boxed(target, area, theme.surface, theme.border)?;
+                let inner = area.inset(2);
+                let done = inner.size.width * percent as u32 / 100;
+                fill(
+                    target,
+                    Area::new(inner.top_left, Size::new(done, inner.size.height)),
+                    theme.accent,
+                )?;
+            }
+
+            Kind::List {
+                items,
+                selected,
+                top,
+            } => {
+                boxed(target, area, theme.surface, border)?;
+                let inner = area.inset(1);
+                for row in 0..self.visible_rows() {
+                    let i = top + row;
+                    let row_area = Area::new(
+                        inner.top_left + Point::new(0, (row as u32 * ROW) as i32),
+                        Size::new(inner.size.width, ROW),
+                    );
+                    if i == selected && i < items.len() {
+                        fill(target, row_area, theme.accent)?;
+                    }
+                    if let Some(item) = items.get(i) {
+                        let color = if i == selected { theme.background } else { fg };
+                        text_at(
+                            target,
+                            row_area.top_left + Point::new(2, 2),
+                            row_area.size.width.saturating_sub(4),
+                            item,
+                            color,
+                        )?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sets `dst`, true if that changed it
+fn replace<T: PartialEq>(dst: &mut T, value: T) -> bool {
+    let changed = *dst != value;
+    *dst = value;
+    changed
+}
+
+fn fill<T>(target: &mut T, area: Area, color: Rgb565) -> Result<(), T::Error>
+where
+    T: DrawTarget<Rgb565>,
+{
+    if area.size.width == 0 || area.size.height == 0 {
+        return Ok(());
+    }
+    area.rectangle()
+        .into_styled(PrimitiveStyle::with_fill(color))
+        .draw(target)
+}
+
+/// Filled box with a one pixel border
+fn boxed<T>(target: &mut T, area: Area, face: Rgb565, border: Rgb565) -> Result<(), T::Error>
+where
+    T: DrawTarget<Rgb565>,
+{
+    area.rectangle()
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(face)
+                .stroke_color(border)
+                .stroke_width(1)
+                .build(),
+        )
+        .draw(target)
+}
+
+/// Text cut to what fits into `width`
+fn text_at<T>(
+    target: &mut T,
+    top_left: Point,
+    width: u32,
+    text: &str,
+    color: Rgb565,
+) -> Result<(), T::Error>
+where
+    T: DrawTarget<Rgb565>,
+{
+    let fits = (width / CHAR_W) as usize;
+    let end = text.char_indices().nth(fits).map_or(text.len(), |(i, _)| i);
+    Text::new(&text[..end], top_left)
+        .into_styled(TextStyle::new(Font6x8, color))
+        .draw(target)
+}
+
+fn centered_text<T>(target: &mut T, area: Area, text: &str, color: Rgb565) -> Result<(), T::Error>
+where
+    T: DrawTarget<Rgb565>,
+{
+    let chars = (text.chars().count() as u32).min(area.size.width / CHAR_W);
+    let top_left = area.center(Size::new(chars * CHAR_W, CHAR_H));
+    text_at(target, top_left, area.size.width, text, color)
+}
@@ -0,0 +1,292 @@
+//
+// Independent watchdog (IWDG) supervision
+//
+// Tasks register with a deadline and check in while they run. A periodic RTIC
+// task at the highest priority calls `service`, which feeds the IWDG only while
+// every armed task checked in within its deadline. Once one misses it the
+// dog isn't fed again and resets the chip, the task's name is kept in `.uninit`
+// RAM for the next boot (`take_starved`), next to the RCC reset flags (`ResetCause`).
+// A hang with interrupts off (or in `service` itself) starves the IWDG directly.
+//
+// Tasks that only run now and then (a touch sampler, a serial handler) `pause`
+// when they go quiet, `check_in` arms them again:
+//
+//   let ui = supervisor.register("ui loop", 500, now).unwrap();
+//   supervisor.start(WATCHDOG_TIMEOUT_MS);
+//
+//   loop {
+//       supervisor.lock(|s| s.check_in(ui, DWT::cycle_count()));
+//       ..
+//   }
+//
+//   #[task(resources = [supervisor], schedule = [watchdog], priority = 3)]
+//   fn watchdog(cx: watchdog::Context) {
+//       cx.resources.supervisor.service(DWT::cycle_count()).ok();
+//       cx.schedule.watchdog(cx.scheduled + ..SERVICE_PERIOD_MS..).ok();
+//   }
+//
+// The IWDG runs from the ~40kHz LSI (30 to 60kHz), give timeouts some slack.
+// It can't be stopped once started, `Board::new` stops it while the core is
+// halted by a debugger.
+//
+use core::{mem::MaybeUninit, ptr};
+
+use stm32f1xx_hal::{pac::RCC, prelude::*, rcc::Clocks, watchdog::IndependentWatchdog};
+
+use embedded_hal::watchdog::{Watchdog, WatchdogEnable};
+
+/// Tasks supervised at most
+pub const MAX_TASKS: usize = 8;
+
+/// Task name bytes kept over a reset
+pub const NAME_LEN: usize = 16;
+
+/// "WDG1", a starved task was saved
+const MAGIC: u32 = 0x5744_4731;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchdogError {
+    /// MAX_TASKS registered already
+    Full,
+}
+
+/// What reset the chip last, from the RCC_CSR flags
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetCause {
+    PowerOn,
+    /// NRST, e.g. the reset button or a debugger
+    Pin,
+    /// SYSRESETREQ
+    Software,
+    IndependentWatchdog,
+    WindowWatchdog,
+    /// Entering standby or stop with the option bytes set so
+    LowPower,
+    Unknown,
+}
+
+impl ResetCause {
+    /// Reads and clears the flags, once at boot (`Board::new` does)
+    pub fn take() -> Self {
+        // `constrain`ed RCC hands out no access to CSR
+        let rcc = unsafe { &*RCC::ptr() };
+        let csr = rcc.csr.read();
+
+        // every reset pulls NRST, the pin flag goes last
+        let cause = if csr.lpwrrstf().bit_is_set() {
+            ResetCause::LowPower
+        } else if csr.wwdgrstf().bit_is_set() {
+            ResetCause::WindowWatchdog
+        } else if csr.iwdgrstf().bit_is_set() {
+            ResetCause::IndependentWatchdog
+        } else if csr.sftrstf().bit_is_set() {
+            ResetCause::Software
+        } else if csr.porrstf().bit_is_set() {
+            ResetCause::PowerOn
+        } else if csr.pinrstf().bit_is_set() {
+            ResetCause::Pin
+        } else {
+            ResetCause::Unknown
+        };
+
+        rcc.csr.modify(|_, w| w.rmvf().set_bit());
+        cause
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ResetCause::PowerOn => "power on",
+            ResetCause::Pin => "reset pin",
+            ResetCause::Software => "software",
+            ResetCause::IndependentWatchdog => "watchdog",
+            ResetCause::WindowWatchdog => "window watchdog",
+            ResetCause::LowPower => "low power",
+            ResetCause::Unknown => "unknown",
+        }
+    }
+
+    pub fn is_watchdog(self) -> bool {
+        matches!(
+            self,
+            ResetCause::IndependentWatchdog | ResetCause::WindowWatchdog
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskId(usize);
+
+#[derive(Debug, Clone, Copy)]
+struct Task {
+    name: &'static str,
+    /// Cycles allowed between check ins
+    deadline: u32,
+    last: u32,
+    armed: bool,
+}
+
+pub struct Supervisor {
+    iwdg: IndependentWatchdog,
+    /// Cycles per ms
+    cycles_per_ms: u32,
+    tasks: [Option<Task>; MAX_TASKS],
+    starved: Option<TaskId>,
+}
+
+impl Supervisor {
+    /// Times check ins on the DWT cycle counter at the frozen SYSCLK,
+    /// the IWDG isn't started yet
+    pub fn new(iwdg: IndependentWatchdog, clocks: &Clocks) -> Self {
+        Supervisor {
+            iwdg,
+            cycles_per_ms: clocks.sysclk().0 / 1_000,
+            tasks: [None; MAX_TASKS],
+            starved: None,
+        }
+    }
+
+    /// Adds a task, armed from `now` (cycles), up to about a minute of `deadline_ms`
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        deadline_ms: u32,
+        now: u32,
+    ) -> Result<TaskId, WatchdogError> {
+        let free = self
+            .tasks
+            .iter()
+            .position(Option::is_none)
+            .ok_or(WatchdogError::Full)?;
+        self.tasks[free] = Some(Task {
+            name,
+            deadline: deadline_ms.saturating_mul(self.cycles_per_ms),
+            last: now,
+            armed: true,
+        });
+        Ok(TaskId(free))
+    }
+
+    /// Starts the IWDG, resets if `service` doesn't feed it for `timeout_ms`
+    pub fn start(&mut self, timeout_ms: u32) {
+        self.iwdg.start(timeout_ms.ms());
+    }
+
+    /// Task `id` is alive at `now` (cycles), arms it when paused
+    pub fn check_in(&mut self, id: TaskId, now: u32) {
+        if let Some(task) = self.tasks[id.0].as_mut() {
+            task.last = now;
+            task.armed = true;
+        }
+    }
+
+    /// Task `id` goes quiet, not supervised until it checks in again
+    pub fn pause(&mut self, id: TaskId) {
+        if let Some(task) = self.tasks[id.0].as_mut() {
+            task.armed = false;
+        }
+    }
+
+    /// Feeds the IWDG when every armed task is on time, otherwise saves the
+    /// first one late and leaves the chip to reset
+    pub fn service(&mut self, now: u32) -> Result<(), TaskId> {
+        if let Some(id) = self.starved {
+            return Err(id);
+        }
+
+        let late = self.tasks.iter().position(|t| match t {
+            Some(t) => t.armed && now.wrapping_sub(t.last) > t.deadline,
+            None => false,
+        });
+        match late {
+            None => {
+                self.iwdg.feed();
+                Ok(())
+            }
+            Some(i) => {
+                let id = TaskId(i);
+                self.starved = Some(id);
+                save(self.name(id));
+                Err(id)
+            }
+        }
+    }
+
+    pub fn name(&self, id: TaskId) -> &'static str {
+        self.tasks[id.0].map_or("", |t| t.name)
+    }
+
+    /// The task that missed its deadline, the IWDG is left to reset
+    pub fn starved(&self) -> Option<TaskId> {
+        self.starved
+    }
+}
+
+/// Task that starved the watchdog before the last reset
+#[derive(Debug, Clone, Copy)]
+pub struct Starved {
+    name: [u8; NAME_LEN],
+    len: usize,
+}
+
+impl Starved {
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.len]).unwrap_or("?")
+    }
+}
+
+/// Kept across resets, garbage after power up: the magic and the check tell
+#[repr(C)]
+struct Saved {
+    magic: u32,
+    len: u32,
+    name: [u8; NAME_LEN],
+    check: u32,
+}
+
+impl Saved {
+    fn checksum(&self) -> u32 {
+        self.name
+            .iter()
+            .fold(self.magic ^ self.len, |s, b| s.rotate_left(5) ^ *b as u32)
+    }
+}
+
+#[link_section = ".uninit.WATCHDOG"]
+static mut SAVED: MaybeUninit<Saved> = MaybeUninit::uninit();
+
+/// Raw, references to a `static mut` are out
+fn saved_ptr() -> *mut Saved {
+    ptr::addr_of_mut!(SAVED).cast()
+}
+
+fn save(name: &str) {
+    // cut at a char boundary
+    let mut len = name.len().min(NAME_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    let mut saved = Saved {
+        magic: MAGIC,
+        len: len as u32,
+        name: [0; NAME_LEN],
+        check: 0,
+    };
+    saved.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+    saved.check = saved.checksum();
+    unsafe { ptr::write_volatile(saved_ptr(), saved) }
+}
+
+/// The task that starved the watchdog before the last reset, once.
+/// Only means something after an `IndependentWatchdog` reset.
+pub fn take_starved() -> Option<Starved> {
+    let saved = unsafe { ptr::read_volatile(saved_ptr()) };
+    if saved.magic != MAGIC || saved.check != saved.checksum() {
+        return None;
+    }
+    unsafe { ptr::write_volatile(ptr::addr_of_mut!((*saved_ptr()).magic), 0) }
+
+    Some(Starved {
+        name: saved.name,
+        len: (saved.len as usize).min(NAME_LEN),
+    })
+}
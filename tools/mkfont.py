@@ -0,0 +1,217 @@
+#!/usr/bin/env python3
+#
+# Converts a BDF bitmap font, or a TTF/OTF font rendered at a pixel size,
+# into the glyph format read by src/font.rs
+#
+#   mkfont.py unifont.bdf unifont.fnt --chars 32-126,0x400-0x45f
+#   mkfont.py DejaVuSans.ttf dejavu16.fnt --size 16 --chars 32-255
+#   mkfont.py NotoSansCJK.otf cjk16.fnt --size 16 --chars 32-126 --text strings.txt
+#
+# Only characters picked by --chars and --text are kept, e.g. the CJK
+# characters actually used by the firmware. TTF rendering needs Pillow,
+# TTF kerning pairs are measured between the --kern characters.
+#
+import argparse
+import struct
+import sys
+
+VERSION = 1
+DEFAULT_CHARS = "32-126"
+DEFAULT_KERN = "32-126"
+
+
+class Glyph:
+    def __init__(self, width, height, left, top, advance, rows):
+        self.width = width
+        self.height = height
+        self.left = left
+        self.top = top
+        self.advance = advance
+        # one int per row, MSB first, padded to a byte
+        self.rows = rows
+
+    def bitmap(self):
+        stride = (self.width + 7) // 8
+        return b"".join(r.to_bytes(stride, "big") for r in self.rows)
+
+
+def parse_ranges(spec):
+    chars = set()
+    for part in filter(None, spec.split(",")):
+        lo, _, hi = part.partition("-")
+        lo = int(lo, 0)
+        hi = int(hi, 0) if hi else lo
+        chars.update(range(lo, hi + 1))
+    return chars
+
+
+def load_bdf(path, wanted):
+    glyphs = {}
+    ascent = descent = None
+    code = None
+
+    with open(path, encoding="latin-1") as f:
+        lines = iter(f.read().splitlines())
+
+    for line in lines:
+        key, _, value = line.partition(" ")
+        if key == "FONT_ASCENT":
+            ascent = int(value)
+        elif key == "FONT_DESCENT":
+            descent = int(value)
+        elif key == "ENCODING":
+            code = int(value.split()[0])
+        elif key == "DWIDTH":
+            advance = int(value.split()[0])
+        elif key == "BBX":
+            width, height, xoff, yoff = map(int, value.split())
+        elif key == "BITMAP":
+            rows = []
+            for _ in range(height):
+                bits = next(lines).strip()
+                row = int(bits, 16) if bits else 0
+                # hex rows are padded to a byte already, drop extra bytes
+                row >>= len(bits) * 4 - (width + 7) // 8 * 8
+                rows.append(row)
+            if code in wanted:
+                glyphs[code] = Glyph(width, height, xoff, yoff + height, advance, rows)
+
+    if ascent is None or descent is None:
+        sys.exit("%s: FONT_ASCENT/FONT_DESCENT missing" % path)
+    return glyphs, ascent, ascent + descent, {}
+
+
+def load_ttf(path, size, wanted, kern_chars):
+    from PIL import ImageFont
+
+    font = ImageFont.truetype(path, size)
+    ascent, descent = font.getmetrics()
+    glyphs = {}
+
+    for code in wanted:
+        ch = chr(code)
+        mask = font.getmask(ch, mode="1")
+        x0, y0, x1, y1 = font.getbbox(ch)
+        advance = int(round(font.getlength(ch)))
+        width, height = mask.size
+        if width == 0 or height == 0:
+            # blank, e.g. space
+            glyphs[code] = Glyph(0, 0, 0, 0, advance, [])
+            continue
+
+        stride = (width + 7) // 8
+        rows = []
+        for y in range(height):
+            row = 0
+            for x in range(width):
+                if mask.getpixel((x, y)):
+                    row |= 1 << (stride * 8 - 1 - x)
+            rows.append(row)
+        # bbox y is from the top of the line, the format counts up from the baseline
+        glyphs[code] = Glyph(width, height, x0, ascent - y0, advance, rows)
+
+    # whatever the layout engine moves a pair by beyond the two advances
+    kerning = {}
+    kern = sorted(c for c in kern_chars if c in glyphs)
+    for a in kern:
+        for b in kern:
+            pair = chr(a) + chr(b)
+            adjust = round(
+                font.getlength(pair) - font.getlength(chr(a)) - font.getlength(chr(b))
+            )
+            if adjust:
+                kerning[(a, b)] = max(-128, min(127, adjust))
+
+    return glyphs, ascent, ascent + descent, kerning
+
+
+def encode(glyphs, ascent, line_height, kerning):
+    table = b""
+    bitmaps = b""
+    for code in sorted(glyphs):
+        g = glyphs[code]
+        if not (0 <= g.width < 256 and 0 <= g.height < 256 and 0 <= g.advance < 256):
+            sys.exit("U+%04X: glyph too large" % code)
+        table += struct.pack(
+            "<IIBBbbBB",
+            code,
+            len(bitmaps),
+            g.width,
+            g.height,
+            g.left,
+            g.top,
+            g.advance,
+            0,
+        )
+        bitmaps += g.bitmap()
+
+    pairs = b"".join(
+        struct.pack("<IIb", a, b, kerning[(a, b)]) for a, b in sorted(kerning)
+    )
+
+    header = b"FN" + struct.pack(
+        "<BBBBHHI",
+        VERSION,
+        line_height,
+        ascent,
+        0,
+        len(glyphs),
+        len(kerning),
+        len(bitmaps),
+    )
+    return header + table + pairs + bitmaps
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__)
+    parser.add_argument("input", help="BDF, TTF or OTF font")
+    parser.add_argument("output")
+    parser.add_argument("--size", type=int, help="pixel size for TTF/OTF")
+    parser.add_argument(
+        "--chars",
+        default=DEFAULT_CHARS,
+        help="code point ranges, e.g. 32-126,0x400-0x45f",
+    )
+    parser.add_argument(
+        "--text",
+        action="append",
+        default=[],
+        help="also keep the characters of a UTF-8 file",
+    )
+    parser.add_argument(
+        "--kern",
+        default=DEFAULT_KERN,
+        help="code point ranges to measure kerning between",
+    )
+    args = parser.parse_args()
+
+    wanted = parse_ranges(args.chars) | {0xFFFD}
+    for path in args.text:
+        with open(path, encoding="utf-8") as f:
+            wanted.update(ord(c) for c in f.read() if c.isprintable())
+
+    if args.input.lower().endswith(".bdf"):
+        glyphs, ascent, line_height, kerning = load_bdf(args.input, wanted)
+    else:
+        if not args.size:
+            sys.exit("--size is needed for %s" % args.input)
+        glyphs, ascent, line_height, kerning = load_ttf(
+            args.input, args.size, wanted, parse_ranges(args.kern)
+        )
+
+    if line_height > 255 or len(glyphs) > 0xFFFF or len(kerning) > 0xFFFF:
+        sys.exit("font too large")
+
+    data = encode(glyphs, ascent, line_height, kerning)
+    with open(args.output, "wb") as f:
+        f.write(data)
+
+    missing = len([c for c in wanted if c not in glyphs and c != 0xFFFD])
+    print(
+        "%s: %d glyphs (%d missing), %d kerning pairs, line height %d, %d bytes"
+        % (args.output, len(glyphs), missing, len(kerning), line_height, len(data))
+    )
+
+
+if __name__ == "__main__":
+    main()
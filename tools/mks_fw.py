@@ -0,0 +1,55 @@
+#!/usr/bin/env python3
+#
+# Turns a raw binary (linked with the `mks-bootloader` feature) into the
+# firmware file the Makerbase bootloader picks up from the SD card
+#
+#   mks_fw.py target/blink.bin mkstft28.bin
+#
+# Copy the output to the root of the card and power up; the bootloader
+# flashes it behind itself and renames it (to *.CUR) once done.
+#
+# The bootloader decrypts bytes 320 up to 31040 of the file by XORing them
+# with a 32 byte key (the same scheme as MKS Robin boards), everything else
+# is plain. XOR runs both ways, so the tool also decrypts a stock file.
+#
+import argparse
+import sys
+
+KEY = bytes([
+    0xA3, 0xBD, 0xAD, 0x0D, 0x41, 0x11, 0xBB, 0x8D, 0xDC, 0x80, 0x2D, 0xD0, 0xD2, 0xC4, 0x9B, 0x1E,
+    0x26, 0xEB, 0xE3, 0x33, 0x4A, 0x15, 0xE4, 0x0A, 0xB3, 0xB1, 0x3C, 0x93, 0xBB, 0xAF, 0xF7, 0x3E,
+])
+
+START = 320
+END = 31040
+
+# flash left behind the 28K bootloader (256K part)
+MAX_SIZE = 256 * 1024 - 0x7000
+
+
+def encrypt(data):
+    out = bytearray(data)
+    for i in range(START, min(END, len(out))):
+        out[i] ^= KEY[i & 31]
+    return bytes(out)
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__)
+    parser.add_argument("input", help="raw binary (objcopy -O binary)")
+    parser.add_argument("output", help="firmware file for the SD card")
+    args = parser.parse_args()
+
+    with open(args.input, "rb") as f:
+        data = f.read()
+
+    if len(data) > MAX_SIZE:
+        sys.exit("%s: %d bytes, only %d fit behind the bootloader"
+                 % (args.input, len(data), MAX_SIZE))
+
+    with open(args.output, "wb") as f:
+        f.write(encrypt(data))
+
+
+if __name__ == "__main__":
+    main()
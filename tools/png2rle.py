@@ -0,0 +1,103 @@
+#!/usr/bin/env python3
+#
+# Converts an image (PNG or anything else Pillow can read) into the
+# run length encoded Rgb565 format decoded by src/image/rle.rs
+#
+#   png2rle.py splash.png splash.rle
+#
+# Uses a palette when the image has at most 256 distinct Rgb565 colors,
+# raw Rgb565 colors otherwise.
+#
+import argparse
+import struct
+import sys
+
+from PIL import Image
+
+MAX_RUN = 1 << 14
+MAX_LITERALS = 64
+
+
+def rgb565(r, g, b):
+    return ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3)
+
+
+def runs(pixels):
+    i = 0
+    while i < len(pixels):
+        j = i + 1
+        while j < len(pixels) and pixels[j] == pixels[i] and j - i < MAX_RUN:
+            j += 1
+        yield pixels[i], j - i
+        i = j
+
+
+def encode(pixels, color_bytes):
+    out = bytearray()
+    literals = []
+
+    def flush_literals():
+        while literals:
+            chunk = literals[:MAX_LITERALS]
+            del literals[:MAX_LITERALS]
+            out.append(0xC0 | (len(chunk) - 1))
+            for c in chunk:
+                out.extend(color_bytes(c))
+
+    for color, n in runs(pixels):
+        if n == 1:
+            literals.append(color)
+            continue
+
+        flush_literals()
+        if n <= 128:
+            out.append(n - 1)
+        else:
+            out.append(0x80 | ((n - 1) >> 8))
+            out.append((n - 1) & 0xFF)
+        out.extend(color_bytes(color))
+
+    flush_literals()
+    return out
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__)
+    parser.add_argument("input")
+    parser.add_argument("output")
+    parser.add_argument(
+        "--raw", action="store_true", help="never use a palette"
+    )
+    args = parser.parse_args()
+
+    img = Image.open(args.input).convert("RGB")
+    width, height = img.size
+    if width > 0xFFFF or height > 0xFFFF:
+        sys.exit("image too large")
+
+    pixels = [rgb565(*p) for p in img.getdata()]
+    colors = sorted(set(pixels))
+
+    if not args.raw and len(colors) <= 256:
+        index = {c: i for i, c in enumerate(colors)}
+        palette = b"".join(struct.pack("<H", c) for c in colors)
+        packets = encode(pixels, lambda c: bytes([index[c]]))
+    else:
+        colors = []
+        palette = b""
+        packets = encode(pixels, lambda c: struct.pack("<H", c))
+
+    header = b"RL" + struct.pack("<HHH", width, height, len(colors))
+
+    with open(args.output, "wb") as f:
+        f.write(header + palette + packets)
+
+    total = len(header) + len(palette) + len(packets)
+    print(
+        "%s: %dx%d, %d colors, %d bytes (raw %d)"
+        % (args.output, width, height, len(colors), total, width * height * 2)
+    )
+
+
+if __name__ == "__main__":
+    main()